@@ -0,0 +1,63 @@
+use std::borrow::Cow;
+use std::io::SeekFrom;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use crate::mdx::{KeyMaker, Mdx, RecordOffset, WordDefinition};
+use crate::parser::{check_block_size, decode_block, decode_definition, find_entry, Limits, record_offset};
+use crate::{Error, Result};
+
+/// dictionary with record blocks read asynchronously,
+/// key entries are parsed into memory when building
+pub struct AsyncMDict<M: KeyMaker> {
+	pub(crate) mdx: Mdx,
+	pub(crate) file: File,
+	pub(crate) key_maker: M,
+}
+
+impl<M: KeyMaker> AsyncMDict<M> {
+	pub async fn lookup<'a>(&mut self, word: &'a str) -> Result<Option<WordDefinition<'a>>>
+	{
+		let key = self.key_maker.make(&Cow::Borrowed(word), false);
+		let offset = find_entry(&self.mdx.key_entries, &key)
+			.and_then(|entry| record_offset(&self.mdx.records_info, entry));
+		let Some(offset) = offset else {
+			return Ok(None);
+		};
+		let encoding = self.mdx.encoding;
+		let range = offset.block_offset..offset.entry_end;
+		let cached = self.mdx.record_cache
+			.as_mut()
+			.and_then(|cache| cache.get(&offset.buf_offset));
+		let definition = if let Some(data) = cached {
+			decode_definition(data.get(range.clone()).ok_or(Error::InvalidData)?, encoding)?
+		} else {
+			let data = read_record(
+				&mut self.file,
+				self.mdx.record_block_offset,
+				&offset,
+				&self.mdx.limits).await?;
+			let definition = decode_definition(data.get(range.clone()).ok_or(Error::InvalidData)?, encoding)?;
+			if let Some(cache) = &mut self.mdx.record_cache {
+				cache.put(offset.buf_offset, data);
+			}
+			definition
+		};
+		Ok(Some(WordDefinition { key: word, definition }))
+	}
+
+	pub fn title(&self) -> &str
+	{
+		&self.mdx.info.title
+	}
+}
+
+async fn read_record(file: &mut File, record_block_offset: u64,
+	offset: &RecordOffset, limits: &Limits) -> Result<Vec<u8>>
+{
+	file.seek(SeekFrom::Start(record_block_offset + offset.buf_offset as u64)).await?;
+	check_block_size(offset.record_size, limits.max_block_size)?;
+	let mut data = vec![0; offset.record_size];
+	file.read_exact(&mut data).await?;
+	decode_block(&data, offset.record_size, offset.decomp_size,
+		limits.verify_checksum, limits.max_block_size)
+}
@@ -0,0 +1,107 @@
+use std::num::NonZeroUsize;
+use lru::LruCache;
+
+use crate::{Error, Result};
+
+/// LRU cache of decoded record blocks by buf offset,
+/// bounded by block count or total bytes
+pub(crate) struct RecordCache {
+	blocks: LruCache<usize, Vec<u8>>,
+	bytes: usize,
+	max_bytes: Option<usize>,
+}
+
+impl RecordCache {
+	/// with max_bytes the block count is not limited, None if nothing
+	/// could be cached at all, a capacity of 0 disables the cache
+	pub(crate) fn new(capacity: usize, max_bytes: Option<usize>) -> Option<Self>
+	{
+		let capacity = NonZeroUsize::new(capacity)?;
+		let blocks = match max_bytes {
+			Some(0) => return None,
+			Some(_) => LruCache::unbounded(),
+			None => LruCache::new(capacity),
+		};
+		Some(RecordCache { blocks, bytes: 0, max_bytes })
+	}
+
+	#[inline]
+	pub(crate) fn get(&mut self, key: &usize) -> Option<&Vec<u8>>
+	{
+		self.blocks.get(key)
+	}
+
+	/// a new empty cache with the same limits
+	pub(crate) fn empty_clone(&self) -> Self
+	{
+		let blocks = if self.max_bytes.is_some() {
+			LruCache::unbounded()
+		} else {
+			LruCache::new(self.blocks.cap())
+		};
+		RecordCache { blocks, bytes: 0, max_bytes: self.max_bytes }
+	}
+
+	#[inline]
+	pub(crate) fn contains(&self, key: &usize) -> bool
+	{
+		self.blocks.contains(key)
+	}
+
+	/// least recently used blocks are evicted to fit the new one,
+	/// the new block is always kept even if larger than the limit
+	pub(crate) fn put(&mut self, key: usize, block: Vec<u8>)
+	{
+		self.bytes += block.len();
+		if let Some((_, old)) = self.blocks.push(key, block) {
+			self.bytes -= old.len();
+		}
+		if let Some(max_bytes) = self.max_bytes {
+			while self.bytes > max_bytes && self.blocks.len() > 1 {
+				if let Some((_, old)) = self.blocks.pop_lru() {
+					self.bytes -= old.len();
+				}
+			}
+		}
+	}
+
+	pub(crate) fn try_get_or_insert<F>(&mut self, key: usize, f: F) -> Result<&Vec<u8>>
+		where F: FnOnce() -> Result<Vec<u8>>
+	{
+		if !self.blocks.contains(&key) {
+			let block = f()?;
+			self.put(key, block);
+		}
+		self.blocks.get(&key).ok_or(Error::InvalidData)
+	}
+
+	/// make room for at least the given number of blocks,
+	/// the byte limit is lifted so none of them is evicted
+	pub(crate) fn reserve(&mut self, blocks: NonZeroUsize)
+	{
+		self.max_bytes = None;
+		if self.blocks.cap() < blocks {
+			self.blocks.resize(blocks);
+		}
+	}
+
+	/// drop all cached blocks, limits are kept
+	pub(crate) fn clear(&mut self)
+	{
+		self.blocks.clear();
+		self.bytes = 0;
+	}
+
+	#[inline]
+	pub(crate) fn len(&self) -> usize
+	{
+		self.blocks.len()
+	}
+
+	/// total bytes of cached blocks
+	#[inline]
+	pub(crate) fn bytes(&self) -> usize
+	{
+		self.bytes
+	}
+}
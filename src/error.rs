@@ -6,8 +6,17 @@ pub enum Error {
 	#[error("Invalid Path: {0}")]
 	InvalidPath(PathBuf),
 
-	#[error("Failed to reading: {0}")]
-	FailedReading(std::io::Error),
+	#[error("MDX file stem contains non-UTF-8 bytes; rename the file: {0}")]
+	NonUtf8FileStem(PathBuf),
+
+	#[error("Dictionary is not loaded from a file")]
+	NoFilePath,
+
+	#[error("Failed to read: {0}")]
+	FailedReading(#[from] std::io::Error),
+
+	#[error("Failed to write: {0}")]
+	FailedWriting(#[source] std::io::Error),
 
 	#[error("Invalid mdx {0} checksum")]
 	InvalidCheckSum(&'static str),
@@ -35,12 +44,83 @@ pub enum Error {
 
 	#[error("Invalid compress method: {0}")]
 	InvalidCompressMethod(u32),
+
+	#[error("Unsupported checksum type: {0}")]
+	UnsupportedChecksumType(u8),
+
+	#[error("Invalid glob pattern: {0}")]
+	InvalidGlob(#[source] glob::PatternError),
+
+	#[error("Too many redirects, from {from} to {to}")]
+	RedirectLoop {
+		from: String,
+		to: String,
+	},
+
+	#[error("Block too large: {0} bytes decompressed")]
+	BlockTooLarge(usize),
+
+	#[error("Invalid regex: {0}")]
+	InvalidRegex(#[source] regex::Error),
+
+	#[error("{context}: {source}")]
+	WithContext {
+		source: Box<Error>,
+		context: String,
+	},
+}
+
+impl Error {
+	/// wrap with where the error happened
+	pub(crate) fn context(self, context: impl Into<String>) -> Self
+	{
+		Error::WithContext { source: Box::new(self), context: context.into() }
+	}
+
+	/// the error without any context wrapped
+	pub fn root(&self) -> &Error
+	{
+		match self {
+			Error::WithContext { source, .. } => source.root(),
+			err => err,
+		}
+	}
 }
 
-impl From<std::io::Error> for Error {
-	fn from(value: std::io::Error) -> Self
+impl Clone for Error {
+	fn clone(&self) -> Self
 	{
-		Error::FailedReading(value)
+		match self {
+			Error::InvalidPath(path) => Error::InvalidPath(path.clone()),
+			Error::NonUtf8FileStem(path) => Error::NonUtf8FileStem(path.clone()),
+			Error::NoFilePath => Error::NoFilePath,
+			Error::FailedReading(e) => Error::FailedReading(
+				std::io::Error::new(e.kind(), e.to_string())),
+			Error::FailedWriting(e) => Error::FailedWriting(
+				std::io::Error::new(e.kind(), e.to_string())),
+			Error::InvalidCheckSum(name) => Error::InvalidCheckSum(name),
+			Error::NoVersion => Error::NoVersion,
+			Error::InvalidVersion(version) => Error::InvalidVersion(version.clone()),
+			Error::NoTitle => Error::NoTitle,
+			Error::UnsupportedVersion(version) => Error::UnsupportedVersion(*version),
+			Error::InvalidData => Error::InvalidData,
+			Error::InvalidEncoding(encoding) => Error::InvalidEncoding(encoding.clone()),
+			Error::InvalidEncryptMethod(method) => Error::InvalidEncryptMethod(*method),
+			Error::InvalidCompressMethod(method) => Error::InvalidCompressMethod(*method),
+			Error::UnsupportedChecksumType(marker) => Error::UnsupportedChecksumType(*marker),
+			Error::InvalidGlob(err) => Error::InvalidGlob(
+				glob::PatternError { pos: err.pos, msg: err.msg }),
+			Error::RedirectLoop { from, to } => Error::RedirectLoop {
+				from: from.clone(),
+				to: to.clone(),
+			},
+			Error::BlockTooLarge(size) => Error::BlockTooLarge(*size),
+			Error::InvalidRegex(err) => Error::InvalidRegex(err.clone()),
+			Error::WithContext { source, context } => Error::WithContext {
+				source: source.clone(),
+				context: context.clone(),
+			},
+		}
 	}
 }
 
@@ -0,0 +1,163 @@
+use std::borrow::Cow;
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use byteorder::{LE, ReadBytesExt, WriteBytesExt};
+use encoding_rs::Encoding;
+
+use crate::{Error, Result};
+use crate::mdx::{BlockEntryInfo, KeyBlockRange, KeyEntry, KeyMaker, Mdx};
+use crate::parser::{KeyIndex, Limits};
+
+const MAGIC: &[u8; 6] = b"MDXIDX";
+/// bumped whenever the layout changes, older files are rebuilt
+const FORMAT_VERSION: u16 = 2;
+/// keys depend on the key maker, this is made into the index to tell
+/// if it was built with another one
+const KEY_MAKER_PROBE: &str = "Index Probe ＡＢ ǅ Ⅻ ü 漢";
+
+/// what the key maker makes of the probe along with every other option
+/// changing the decoded keys, an index built with different ones is stale
+pub(crate) fn index_fingerprint(key_maker: &dyn KeyMaker,
+	encoding_override: Option<&'static Encoding>, limits: &Limits, resource: bool) -> String
+{
+	format!("{}\0{}\0{}\0{}",
+		key_maker.make(&Cow::Borrowed(KEY_MAKER_PROBE), resource),
+		encoding_override.map_or("", |encoding| encoding.name()),
+		limits.max_key_length,
+		resource)
+}
+
+/// save parsed keys and record index of mdx loaded from mdx_path.
+/// Layout, little endian: magic, format version u16, mdx file size u64,
+/// index fingerprint, entry count u64, key blocks, key entries,
+/// record blocks and record block offset u64. Lists are prefixed by
+/// u64 length, strings by u32 length in bytes.
+pub(crate) fn write_index(path: &Path, mdx: &Mdx, mdx_path: &Path, fingerprint: &str)
+	-> Result<()>
+{
+	let mdx_len = fs::metadata(mdx_path)?.len();
+	let mut writer = BufWriter::new(File::create(path).map_err(Error::FailedWriting)?);
+	write_all(&mut writer, mdx, mdx_len, fingerprint)
+		.and_then(|_| writer.flush())
+		.map_err(Error::FailedWriting)
+}
+
+pub(crate) fn write_all(writer: &mut impl Write, mdx: &Mdx, mdx_len: u64, fingerprint: &str)
+	-> std::io::Result<()>
+{
+	writer.write_all(MAGIC)?;
+	writer.write_u16::<LE>(FORMAT_VERSION)?;
+	writer.write_u64::<LE>(mdx_len)?;
+	write_text(writer, fingerprint)?;
+	writer.write_u64::<LE>(mdx.entry_count as u64)?;
+	writer.write_u64::<LE>(mdx.key_blocks.len() as u64)?;
+	for block in mdx.key_blocks.iter() {
+		writer.write_u64::<LE>(block.entry_count as u64)?;
+		write_text(writer, &block.first_key)?;
+		write_text(writer, &block.last_key)?;
+	}
+	writer.write_u64::<LE>(mdx.key_entries.len() as u64)?;
+	for entry in mdx.key_entries.iter() {
+		writer.write_u64::<LE>(entry.offset as u64)?;
+		writer.write_u64::<LE>(entry.end.map_or(u64::MAX, |end| end as u64))?;
+		write_text(writer, &entry.text)?;
+	}
+	writer.write_u64::<LE>(mdx.records_info.len() as u64)?;
+	for info in mdx.records_info.iter() {
+		writer.write_u64::<LE>(info.compressed_size as u64)?;
+		writer.write_u64::<LE>(info.decompressed_size as u64)?;
+	}
+	writer.write_u64::<LE>(mdx.record_block_offset)
+}
+
+#[inline]
+fn write_text(writer: &mut impl Write, text: &str) -> std::io::Result<()>
+{
+	writer.write_u32::<LE>(text.len() as u32)?;
+	writer.write_all(text.as_bytes())
+}
+
+/// index saved by write_index, None if it is missing, older than the
+/// dictionary, built with another key maker or format, or corrupt
+pub(crate) fn read_index(path: &Path, mdx_path: &Path, fingerprint: &str) -> Option<KeyIndex>
+{
+	let index_meta = fs::metadata(path).ok()?;
+	let mdx_meta = fs::metadata(mdx_path).ok()?;
+	if index_meta.modified().ok()? < mdx_meta.modified().ok()? {
+		return None;
+	}
+	let data = fs::read(path).ok()?;
+	read_all(&mut data.as_slice(), mdx_meta.len(), fingerprint).ok()?
+}
+
+pub(crate) fn read_all(reader: &mut &[u8], mdx_len: u64, fingerprint: &str)
+	-> std::io::Result<Option<KeyIndex>>
+{
+	let mut magic = [0; 6];
+	reader.read_exact(&mut magic)?;
+	if &magic != MAGIC
+		|| reader.read_u16::<LE>()? != FORMAT_VERSION
+		|| reader.read_u64::<LE>()? != mdx_len
+		|| read_text(reader)? != fingerprint {
+		return Ok(None);
+	}
+	let entry_count = read_size(reader)?;
+	let mut key_blocks = vec![];
+	for _ in 0..read_size(reader)? {
+		let entry_count = read_size(reader)?;
+		let first_key = read_text(reader)?;
+		let last_key = read_text(reader)?;
+		key_blocks.push(KeyBlockRange { first_key, last_key, entry_count });
+	}
+	let mut key_entries = vec![];
+	for _ in 0..read_size(reader)? {
+		let offset = read_size(reader)?;
+		let end = match reader.read_u64::<LE>()? {
+			u64::MAX => None,
+			end => Some(end as usize),
+		};
+		let text = read_text(reader)?;
+		key_entries.push(KeyEntry { offset, end, text });
+	}
+	let mut records_info = vec![];
+	for _ in 0..read_size(reader)? {
+		let compressed_size = read_size(reader)?;
+		let decompressed_size = read_size(reader)?;
+		records_info.push(BlockEntryInfo {
+			compressed_size,
+			decompressed_size,
+			entry_count: 0,
+		});
+	}
+	let record_block_offset = reader.read_u64::<LE>()?;
+	if !reader.is_empty() {
+		return Ok(None);
+	}
+	Ok(Some(KeyIndex {
+		entry_count,
+		key_blocks,
+		key_entries,
+		records_info,
+		record_block_offset,
+	}))
+}
+
+#[inline]
+fn read_size(reader: &mut &[u8]) -> std::io::Result<usize>
+{
+	Ok(reader.read_u64::<LE>()? as usize)
+}
+
+fn read_text(reader: &mut &[u8]) -> std::io::Result<String>
+{
+	let len = reader.read_u32::<LE>()? as usize;
+	if reader.len() < len {
+		return Err(std::io::ErrorKind::UnexpectedEof.into());
+	}
+	let (text, rest) = reader.split_at(len);
+	let text = String::from_utf8(text.to_vec())
+		.map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidData))?;
+	*reader = rest;
+	Ok(text)
+}
@@ -1,32 +1,973 @@
 mod mdx;
 mod error;
 mod parser;
+mod cache;
+mod normalize;
+mod shared;
+mod index;
+#[cfg(feature = "async")]
+mod async_mdx;
 
 pub use crate::mdx::MDict;
+pub use crate::mdx::IntoEntries;
+pub use crate::mdx::lookup_multi_dict;
 pub use crate::mdx::MDictBuilder;
 pub use crate::mdx::KeyMaker;
+pub use crate::mdx::DefaultKeyMaker;
+pub use crate::shared::SharedMDict;
+pub use crate::normalize::{fold_key, KeyMakerChain, soundex_key_maker};
+#[cfg(feature = "pinyin")]
+pub use crate::normalize::PinyinKeyMaker;
+pub use crate::parser::{is_resource_dict, peek_version};
+pub use crate::mdx::Nearest;
+pub use crate::mdx::LoadProgress;
 pub use crate::mdx::WordDefinition;
+pub use crate::mdx::OwnedWordDefinition;
+pub use crate::mdx::DictInfo;
+pub use crate::mdx::DictStats;
+pub use crate::mdx::KeyBlockRange;
+pub use crate::mdx::VerifyReport;
+pub use crate::mdx::ValidationReport;
+#[cfg(feature = "async")]
+pub use crate::async_mdx::AsyncMDict;
 pub use crate::error::Error;
 pub use crate::error::Result;
 
 #[cfg(test)]
 mod tests {
 	use std::borrow::Cow;
-	use crate::MDictBuilder;
+	use std::error::Error as _;
+	use std::io;
+	use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+	use static_assertions::assert_impl_all;
+	use crate::{DefaultKeyMaker, Error, fold_key, KeyMaker, KeyMakerChain, MDict, MDictBuilder, SharedMDict, soundex_key_maker, WordDefinition};
+	use crate::parser::{decode_definition, decode_slice_string, edit_distance, HEADER_FALLBACK_ENCODINGS, html_to_text, read_header_text, resource_path, salsa20_8_decrypt, strip_html_wrapper, wildcard_match};
+
+	assert_impl_all!(MDictBuilder: Send, Sync);
+	assert_impl_all!(MDict<DefaultKeyMaker>: Send, Sync);
+	assert_impl_all!(SharedMDict<DefaultKeyMaker>: Send, Sync, Clone);
+	assert_impl_all!(MDict<KeyMakerChain>: Send, Sync);
 
 	const MDX_V2: &str = "/home/zl/dicts/漢語大字典/漢語大字典 (2010).mdx";
 
+	/// uncompressed block with adler32 checksum
+	fn raw_block(data: &[u8]) -> Vec<u8>
+	{
+		let mut block = vec![0, 0, 0, 0];
+		block.extend(adler32::RollingAdler32::from_buffer(data).hash().to_be_bytes());
+		block.extend(data);
+		block
+	}
+
+	/// minimal v1 dictionary with the given header text, entries are
+	/// sorted keys in one key block, each record in its own record block
+	fn v1_mdx(header: &[u8], encoding: &'static Encoding, entries: &[(&str, &str)]) -> Vec<u8>
+	{
+		let encode = |text: &str| {
+			let mut bytes = encoding.encode(text).0.into_owned();
+			bytes.push(0);
+			bytes
+		};
+		let mut out = vec![];
+		out.extend((header.len() as u32).to_be_bytes());
+		out.extend(header);
+		out.extend(adler32::RollingAdler32::from_buffer(header).hash().to_le_bytes());
+
+		let records: Vec<Vec<u8>> = entries.iter().map(|(_, definition)| encode(definition)).collect();
+		let mut keys = vec![];
+		let mut offset = 0;
+		for ((key, _), record) in entries.iter().zip(&records) {
+			keys.extend((offset as u32).to_be_bytes());
+			keys.extend(encode(key));
+			offset += record.len();
+		}
+		let key_block = raw_block(&keys);
+		let mut info = vec![];
+		info.extend((entries.len() as u32).to_be_bytes());
+		for key in [entries[0].0, entries[entries.len() - 1].0] {
+			let key = encoding.encode(key).0;
+			info.push(key.len() as u8);
+			info.extend(key.as_ref());
+		}
+		info.extend((key_block.len() as u32).to_be_bytes());
+		info.extend((keys.len() as u32).to_be_bytes());
+		for number in [1, entries.len(), info.len(), key_block.len()] {
+			out.extend((number as u32).to_be_bytes());
+		}
+		out.extend(info);
+		out.extend(key_block);
+
+		let blocks: Vec<Vec<u8>> = records.iter().map(|record| raw_block(record)).collect();
+		for number in [blocks.len(), entries.len(), blocks.len() * 8,
+			blocks.iter().map(Vec::len).sum()] {
+			out.extend((number as u32).to_be_bytes());
+		}
+		for (block, record) in blocks.iter().zip(&records) {
+			out.extend((block.len() as u32).to_be_bytes());
+			out.extend((record.len() as u32).to_be_bytes());
+		}
+		out.extend(blocks.concat());
+		out
+	}
+
+	fn utf16_header(text: &str) -> Vec<u8>
+	{
+		text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+	}
+
+	const TEST_HEADER: &str = "<Dictionary GeneratedByEngineVersion=\"1.2\" Title=\"test\" Encoding=\"UTF-8\"/>";
+	const TEST_ENTRIES: &[(&str, &str)] = &[("apple", "<b>apple</b>"), ("dog", "<b>dog</b>"), ("zoo", "<b>zoo</b>")];
+
+	#[test]
+	fn decode_utf16be()
+	{
+		// 'a' is 0x00 0x61 in UTF-16BE, a single byte null scan would stop there
+		let data = [0x4e, 0x2d, 0x00, 0x61, 0x00, 0x00, 0x00, 0x62];
+		let (text, len) = decode_slice_string(&data, UTF_16BE).unwrap();
+		assert_eq!(text, "中a");
+		assert_eq!(len, 6);
+	}
+
+	#[test]
+	fn gbk_header()
+	{
+		let xml = r#"<Dictionary GeneratedByEngineVersion="1.2" Title="汉语词典" Encoding="GBK"/>"#;
+		let info = encoding_rs::GBK.encode(xml).0;
+		let mut buf = (info.len() as u32).to_be_bytes().to_vec();
+		buf.extend(info.iter());
+		buf.extend(adler32::RollingAdler32::from_buffer(&info).hash().to_le_bytes());
+		let text = read_header_text(&mut io::Cursor::new(&buf), true, HEADER_FALLBACK_ENCODINGS).unwrap();
+		assert_eq!(text, xml);
+
+		let info: Vec<u8> = xml.encode_utf16().flat_map(u16::to_le_bytes).collect();
+		let mut buf = (info.len() as u32).to_be_bytes().to_vec();
+		buf.extend(info.iter());
+		buf.extend(adler32::RollingAdler32::from_buffer(&info).hash().to_le_bytes());
+		let text = read_header_text(&mut io::Cursor::new(&buf), true, HEADER_FALLBACK_ENCODINGS).unwrap();
+		assert_eq!(text, xml);
+	}
+
+	#[test]
+	fn gbk_dictionary()
+	{
+		let xml = r#"<Dictionary GeneratedByEngineVersion="1.2" Title="汉语词典" Encoding="GBK"/>"#;
+		let entries = [("汉", "<b>汉</b> 汉语"), ("字", "文字")];
+		let data = v1_mdx(&encoding_rs::GBK.encode(xml).0, encoding_rs::GBK, &entries);
+		let mut mdict = MDictBuilder::from_bytes(data).build().unwrap();
+		assert_eq!(mdict.title(), "汉语词典");
+		assert_eq!(mdict.info().encoding, "GBK");
+		for (key, definition) in entries {
+			assert_eq!(mdict.lookup(key).unwrap()[0].definition, definition);
+		}
+	}
+
+	#[test]
+	fn definition_last_char()
+	{
+		assert_eq!(decode_definition(b"abc\0", UTF_8).unwrap(), "abc");
+		assert_eq!(decode_definition(b"abc", UTF_8).unwrap(), "abc");
+		assert_eq!(decode_definition(b"abc\r\n\0", UTF_8).unwrap(), "abc");
+		assert_eq!(decode_definition(b"a\x00b\x00\n\x00\x00\x00", UTF_16LE).unwrap(), "ab");
+	}
+
+	#[test]
+	fn salsa20_8()
+	{
+		use salsa20::Salsa8;
+		use salsa20::cipher::{KeyIvInit, StreamCipher};
+
+		let key: Vec<u8> = (0..32).collect();
+		let data: Vec<u8> = (0..200).map(|i| (i * 7) as u8).collect();
+		let mut expected = data.clone();
+		Salsa8::new(key.as_slice().into(), &[0; 8].into()).apply_keystream(&mut expected);
+		assert_eq!(salsa20_8_decrypt(&data, &key), expected);
+		assert_eq!(expected[..16], [0xba, 0x38, 0xb8, 0x97, 0xf7, 0xa5, 0xf9, 0x56,
+			0x11, 0xbe, 0x92, 0x9a, 0x97, 0xc0, 0xcb, 0x53]);
+		// keystream of 16 bytes key 00..0f, zero nonce, into the second block
+		let stream = salsa20_8_decrypt(&[0; 72], &key[..16]);
+		assert_eq!(stream[..16], [0xb9, 0x44, 0xf9, 0x75, 0xfa, 0x4f, 0x57, 0x82,
+			0x8c, 0xd3, 0x95, 0xec, 0x50, 0x22, 0x44, 0xc7]);
+		assert_eq!(stream[64..], [0xf9, 0x76, 0x09, 0xf4, 0xe5, 0xfe, 0x4c, 0x02]);
+	}
+
+	#[test]
+	fn cjk_fold()
+	{
+		// U+F900 is a compatibility ideograph of U+8C48
+		assert_eq!(fold_key("\u{f900}"), "\u{8c48}");
+		assert_eq!(fold_key("ＡＢｃ"), "abc");
+	}
+
+	#[test]
+	fn unicode_case_fold()
+	{
+		assert_eq!(fold_key("ÜBER"), fold_key("über"));
+		assert_eq!(fold_key("Straße"), fold_key("STRASSE"));
+		assert_eq!(fold_key("ΣΟΦΟΣ"), fold_key("σοφος"));
+	}
+
+	#[test]
+	fn crc32_block()
+	{
+		use crate::parser::{block_checksum_type, decode_block};
+		use crate::mdx::ChecksumType;
+
+		let data = b"crc32 checked definition";
+		let mut block = vec![0, 0, 0, 1];
+		block.extend(crc32fast::hash(data).to_be_bytes());
+		block.extend(data);
+		let checksum_type = block_checksum_type(&block).unwrap();
+		assert_eq!(checksum_type, ChecksumType::Crc32);
+		let decoded = decode_block(&block, block.len(), data.len(), true, data.len()).unwrap();
+		assert_eq!(decoded, data);
+
+		assert!(matches!(decode_block(&block, block.len(), data.len(), true, 8),
+			Err(Error::BlockTooLarge(size)) if size == data.len()));
+
+		// marked adler32, the crc32 checksum fails unless not verified
+		block[3] = 0;
+		assert!(decode_block(&block, block.len(), data.len(), true, data.len()).is_err());
+		assert!(decode_block(&block, block.len(), data.len(), false, data.len()).is_ok());
+
+		block[3] = 7;
+		assert!(matches!(block_checksum_type(&block), Err(Error::UnsupportedChecksumType(7))));
+		assert!(matches!(decode_block(&block, block.len(), data.len(), true, data.len()),
+			Err(Error::UnsupportedChecksumType(7))));
+	}
+
+	#[test]
+	fn partial_encryption()
+	{
+		use ripemd::{Digest, Ripemd128};
+		use crate::parser::{decode_block, salsa20_8_decrypt};
+
+		let data = b"partly salsa20 encrypted definition";
+		let mut block = raw_block(data);
+		let key = Ripemd128::digest(&block[4..8]);
+		// method 2 on the first 4 bytes only
+		block[0] = 0x20;
+		block[1] = 4;
+		let encrypted = salsa20_8_decrypt(&block[8..12], &key);
+		block[8..12].copy_from_slice(&encrypted);
+		assert_ne!(&block[8..12], &data[..4]);
+		let decoded = decode_block(&block, block.len(), data.len(), true, data.len()).unwrap();
+		assert_eq!(decoded, data);
+	}
+
+	#[cfg(feature = "zstd")]
+	#[test]
+	fn zstd_block()
+	{
+		use crate::parser::decode_block;
+
+		let data = b"zstd compressed definition".repeat(4);
+		let mut block = vec![3, 0, 0, 0];
+		block.extend(adler32::RollingAdler32::from_buffer(&data).hash().to_be_bytes());
+		block.extend(zstd::encode_all(data.as_slice(), 0).unwrap());
+		let decoded = decode_block(&block, block.len(), data.len(), true, data.len()).unwrap();
+		assert_eq!(decoded, data);
+	}
+
+	#[test]
+	fn soundex()
+	{
+		let key_maker = soundex_key_maker();
+		let make = |key: &str| key_maker.make(&Cow::Borrowed(key), false);
+		assert_eq!(make("Robert"), "R163");
+		assert_eq!(make("Rupert"), "R163");
+		assert_eq!(make("Ashcraft"), "A261");
+		assert_eq!(make("Tymczak"), "T522");
+		assert_eq!(make("Pfister"), "P236");
+		assert_eq!(make("Lee"), "L000");
+		assert_eq!(make("日本"), "日本");
+		assert_eq!(key_maker.make(&Cow::Borrowed("\\Img.PNG"), true), "\\img.png");
+	}
+
+	#[cfg(feature = "pinyin")]
+	#[test]
+	fn pinyin_key()
+	{
+		use crate::PinyinKeyMaker;
+
+		let make = |key: &str| PinyinKeyMaker.make(&Cow::Borrowed(key), false);
+		assert_eq!(make("无"), "wu");
+		assert_eq!(make("wú"), "wu");
+		assert_eq!(make("Wu2"), "wu");
+		assert_eq!(make("中国"), "zhongguo");
+		assert_eq!(make("zhōng guó"), "zhongguo");
+		assert_eq!(make("绿"), "lv");
+		assert_eq!(make("lǜ"), "lv");
+	}
+
+	#[test]
+	fn record_block_boundary()
+	{
+		use crate::mdx::{BlockEntryInfo, KeyEntry};
+		use crate::parser::record_offset;
+
+		let info = |compressed_size, decompressed_size| BlockEntryInfo {
+			compressed_size,
+			decompressed_size,
+			entry_count: 0,
+		};
+		let records = [info(5, 10), info(7, 20), info(3, 5)];
+		let locate = |offset| record_offset(&records, &KeyEntry { offset, end: None, text: String::new() })
+			.map(|offset| (offset.buf_offset, offset.block_offset));
+		assert_eq!(locate(0), Some((0, 0)));
+		assert_eq!(locate(9), Some((0, 9)));
+		assert_eq!(locate(10), Some((5, 0)));
+		assert_eq!(locate(29), Some((5, 19)));
+		assert_eq!(locate(30), Some((12, 0)));
+		assert_eq!(locate(34), Some((12, 4)));
+		assert_eq!(locate(35), None);
+	}
+
+	#[test]
+	fn encoding_override()
+	{
+		use encoding_rs::{GBK, SHIFT_JIS};
+
+		for encoding in [GBK, SHIFT_JIS] {
+			let entries = [("apple", "蘋果"), ("漢字", "かんじ")];
+			let data = v1_mdx(&utf16_header(TEST_HEADER), encoding, &entries);
+			let mut mdict = MDictBuilder::from_bytes(data)
+				.encoding_override(encoding)
+				.build()
+				.unwrap();
+			for (key, definition) in entries {
+				assert_eq!(mdict.lookup(key).unwrap()[0].definition, definition);
+			}
+		}
+	}
+
+	#[test]
+	fn preload_no_reads()
+	{
+		use std::sync::Arc;
+		use std::sync::atomic::{AtomicUsize, Ordering};
+
+		struct CountingReader(io::Cursor<Vec<u8>>, Arc<AtomicUsize>);
+		impl io::Read for CountingReader {
+			fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+			{
+				self.1.fetch_add(1, Ordering::SeqCst);
+				self.0.read(buf)
+			}
+		}
+		impl io::Seek for CountingReader {
+			fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64>
+			{
+				self.0.seek(pos)
+			}
+		}
+
+		let reads = Arc::new(AtomicUsize::new(0));
+		let data = v1_mdx(&utf16_header(TEST_HEADER), UTF_8, TEST_ENTRIES);
+		let reader = CountingReader(io::Cursor::new(data), reads.clone());
+		let mut mdict = MDictBuilder::from_reader(reader, std::env::temp_dir().join("preload.mdx"))
+			.build()
+			.unwrap();
+		mdict.preload().unwrap();
+		reads.store(0, Ordering::SeqCst);
+		let found = mdict.lookup_batch(&["apple", "dog"]).unwrap();
+		assert!(found.iter().all(Option::is_some));
+		assert_eq!(mdict.entries().count(), 3);
+		assert_eq!(mdict.scan_from("dog").count(), 2);
+		assert_eq!(reads.load(Ordering::SeqCst), 0);
+	}
+
+	#[test]
+	fn index_round_trip()
+	{
+		use crate::index::{read_all, write_all};
+
+		let data = v1_mdx(&utf16_header(TEST_HEADER), UTF_8, TEST_ENTRIES);
+		let mdict = MDictBuilder::from_bytes(data).build().unwrap();
+		let mut buf = vec![];
+		write_all(&mut buf, &mdict.mdx, 1234, "fp").unwrap();
+		let index = read_all(&mut buf.as_slice(), 1234, "fp").unwrap().unwrap();
+		assert_eq!(index.entry_count, mdict.mdx.entry_count);
+		assert_eq!(index.record_block_offset, mdict.mdx.record_block_offset);
+		assert_eq!(index.key_blocks.len(), 1);
+		assert_eq!(index.key_blocks[0].first_key, "apple");
+		assert_eq!(index.key_blocks[0].last_key, "zoo");
+		let entries = |entries: &[crate::mdx::KeyEntry]| entries
+			.iter()
+			.map(|entry| (entry.offset, entry.end, entry.text.clone()))
+			.collect::<Vec<_>>();
+		assert_eq!(entries(&index.key_entries), entries(&mdict.mdx.key_entries));
+		let records = |infos: &[crate::mdx::BlockEntryInfo]| infos
+			.iter()
+			.map(|info| (info.compressed_size, info.decompressed_size))
+			.collect::<Vec<_>>();
+		assert_eq!(records(&index.records_info), records(&mdict.mdx.records_info));
+
+		let rejected = |buf: &[u8], mdx_len, fingerprint| matches!(
+			read_all(&mut &buf[..], mdx_len, fingerprint), Ok(None));
+		assert!(rejected(&buf, 1235, "fp"));
+		assert!(rejected(&buf, 1234, "other"));
+		let mut bad = buf.clone();
+		bad[0] = b'X';
+		assert!(rejected(&bad, 1234, "fp"));
+		let mut bad = buf.clone();
+		bad[6] += 1;
+		assert!(rejected(&bad, 1234, "fp"));
+		let mut bad = buf.clone();
+		bad.push(0);
+		assert!(rejected(&bad, 1234, "fp"));
+		assert!(read_all(&mut &buf[..buf.len() - 1], 1234, "fp").is_err());
+	}
+
+	#[test]
+	fn index_rebuilt_for_options()
+	{
+		let entries = [("apple", "蘋果"), ("漢字", "かんじ")];
+		let data = v1_mdx(&utf16_header(TEST_HEADER), encoding_rs::GBK, &entries);
+		let dir = std::env::temp_dir();
+		let path = dir.join("index_rebuilt_for_options.mdx");
+		let index = dir.join("index_rebuilt_for_options.idx");
+		std::fs::write(&path, data).unwrap();
+		let _ = std::fs::remove_file(&index);
+		// keys are garbled without the override
+		let mut mdict = MDictBuilder::new(&path).index_file(&index).build().unwrap();
+		assert!(mdict.lookup("漢字").unwrap().is_empty());
+		assert!(index.exists());
+		let mut mdict = MDictBuilder::new(&path)
+			.index_file(&index)
+			.encoding_override(encoding_rs::GBK)
+			.build()
+			.unwrap();
+		assert_eq!(mdict.lookup("漢字").unwrap()[0].definition, "かんじ");
+		std::fs::remove_file(path).unwrap();
+		std::fs::remove_file(index).unwrap();
+	}
+
+	#[test]
+	fn indexed_progress()
+	{
+		use std::sync::Mutex;
+		use crate::LoadProgress;
+
+		let data = v1_mdx(&utf16_header(TEST_HEADER), UTF_8, TEST_ENTRIES);
+		let dir = std::env::temp_dir();
+		let path = dir.join("indexed_progress.mdx");
+		let index = dir.join("indexed_progress.idx");
+		std::fs::write(&path, data).unwrap();
+		let _ = std::fs::remove_file(&index);
+		let stages = |expected: &[LoadProgress]| {
+			let reported = Mutex::new(vec![]);
+			MDictBuilder::new(&path)
+				.index_file(&index)
+				.build_with_progress(|key: &Cow<str>, _| key.to_lowercase(),
+					|stage| reported.lock().unwrap().push(stage))
+				.unwrap();
+			assert_eq!(reported.into_inner().unwrap(), expected);
+		};
+		let key_blocks = LoadProgress::ReadingKeyBlocks { done: 1, total: 1 };
+		stages(&[LoadProgress::ReadingHeader, key_blocks,
+			LoadProgress::ReadingRecordIndex, LoadProgress::Done]);
+		assert!(index.exists());
+		stages(&[LoadProgress::ReadingHeader, key_blocks, LoadProgress::Done]);
+		std::fs::remove_file(path).unwrap();
+		std::fs::remove_file(index).unwrap();
+	}
+
+	#[test]
+	fn dump_key_blocks()
+	{
+		let data = v1_mdx(&utf16_header(TEST_HEADER), UTF_8, TEST_ENTRIES);
+		let mdx = MDictBuilder::from_bytes(data).build().unwrap();
+		let key_blocks = mdx.dump_key_blocks();
+		assert_eq!(key_blocks.len(), mdx.stats().total_key_blocks);
+		assert_eq!(key_blocks.len(), 1);
+		assert_eq!(key_blocks[0].first_key, "apple");
+		assert_eq!(key_blocks[0].last_key, "zoo");
+		assert_eq!(key_blocks[0].entry_count, mdx.entry_count());
+		assert_eq!(key_blocks[0].entry_count, 3);
+	}
+
+	#[test]
+	fn key_for_record_offset()
+	{
+		let data = v1_mdx(&utf16_header(TEST_HEADER), UTF_8, TEST_ENTRIES);
+		let mdx = MDictBuilder::from_bytes(data).build().unwrap();
+		// records are NUL terminated, 13, 11 and 11 bytes
+		assert_eq!(mdx.key_for_record_offset(0), Some("apple"));
+		assert_eq!(mdx.key_for_record_offset(12), Some("apple"));
+		assert_eq!(mdx.key_for_record_offset(13), Some("dog"));
+		assert_eq!(mdx.key_for_record_offset(34), Some("zoo"));
+		assert_eq!(mdx.key_for_record_offset(35), None);
+		assert_eq!(mdx.key_for_record_offset(usize::MAX), None);
+	}
+
+	#[test]
+	fn cross_reference_lookup()
+	{
+		let entries = [("a", "@@@LINK=b"), ("b", "@@@LINK=a"), ("c", "@@@LINK=d"),
+			("d", "<b>d</b>"), ("e", "@@@LINK=x")];
+		let data = v1_mdx(&utf16_header(TEST_HEADER), UTF_8, &entries);
+		let mut mdx = MDictBuilder::from_bytes(data.clone()).build().unwrap();
+		assert!(matches!(mdx.cross_reference_lookup("a"),
+			Err(Error::RedirectLoop { from, to }) if from == "b" && to == "a"));
+		assert_eq!(mdx.cross_reference_lookup("c").unwrap().unwrap().definition, "<b>d</b>");
+		assert_eq!(mdx.cross_reference_lookup("e").unwrap().unwrap().definition, "@@@LINK=x");
+		assert!(mdx.cross_reference_lookup("f").unwrap().is_none());
+
+		let mut mdx = MDictBuilder::from_bytes(data.clone())
+			.max_redirect_depth(1)
+			.build()
+			.unwrap();
+		assert_eq!(mdx.cross_reference_lookup("c").unwrap().unwrap().definition, "<b>d</b>");
+		assert!(matches!(mdx.cross_reference_lookup("b"),
+			Err(Error::RedirectLoop { from, to }) if from == "a" && to == "b"));
+
+		let mut mdx = MDictBuilder::from_bytes(data)
+			.max_redirect_depth(0)
+			.build()
+			.unwrap();
+		assert_eq!(mdx.cross_reference_lookup("c").unwrap().unwrap().definition, "@@@LINK=d");
+		assert_eq!(mdx.cross_reference_lookup("a").unwrap().unwrap().definition, "@@@LINK=b");
+	}
+
+	#[test]
+	fn export_keyword()
+	{
+		let header = "<Dictionary GeneratedByEngineVersion=\"1.2\" Title=\"test\" Encoding=\"UTF-8\" ExportKeyword=\"1\"/>";
+		let entries = [("a; m;z", "<b>amz</b>"), ("b", "<b>b</b>")];
+		let data = v1_mdx(&utf16_header(header), UTF_8, &entries);
+		let mut mdx = MDictBuilder::from_bytes(data).build().unwrap();
+		assert_eq!(mdx.keys().collect::<Vec<_>>(), ["a", "b", "m", "z"]);
+		for key in ["a", "m", "z"] {
+			assert_eq!(mdx.lookup(key).unwrap()[0].definition, "<b>amz</b>");
+		}
+		assert_eq!(mdx.lookup("b").unwrap()[0].definition, "<b>b</b>");
+		assert!(mdx.lookup("a; m;z").unwrap().is_empty());
+
+		let data = v1_mdx(&utf16_header(TEST_HEADER), UTF_8, &entries);
+		let mut mdx = MDictBuilder::from_bytes(data).build().unwrap();
+		assert_eq!(mdx.lookup("a; m;z").unwrap()[0].definition, "<b>amz</b>");
+		assert!(mdx.lookup("m").unwrap().is_empty());
+	}
+
+	#[test]
+	fn key_maker_not_send()
+	{
+		let lowercase = std::rc::Rc::new(true);
+		let data = v1_mdx(&utf16_header(TEST_HEADER), UTF_8, TEST_ENTRIES);
+		let builder = std::thread::spawn(move || MDictBuilder::from_bytes(data))
+			.join()
+			.unwrap();
+		let mut mdx = builder
+			.build_with_key_maker(move |key: &Cow<str>, _| if *lowercase {
+				key.to_lowercase()
+			} else {
+				key.to_string()
+			})
+			.unwrap();
+		assert_eq!(mdx.lookup("DOG").unwrap()[0].definition, "<b>dog</b>");
+	}
+
+	#[test]
+	fn reload_reader()
+	{
+		let path = std::env::temp_dir().join("reload_reader.mdx");
+		std::fs::write(&path, v1_mdx(&utf16_header(TEST_HEADER), UTF_8, &[("cat", "<b>cat</b>")])).unwrap();
+		let data = v1_mdx(&utf16_header(TEST_HEADER), UTF_8, TEST_ENTRIES);
+		let mut mdict = MDictBuilder::from_reader(io::Cursor::new(data), &path)
+			.build()
+			.unwrap();
+		assert!(matches!(mdict.reload(), Err(Error::NoFilePath)));
+		assert_eq!(mdict.lookup("dog").unwrap().len(), 1);
+		assert!(mdict.lookup("cat").unwrap().is_empty());
+		std::fs::remove_file(path).unwrap();
+	}
+
+	#[test]
+	fn try_clone_reader()
+	{
+		let data = v1_mdx(&utf16_header(TEST_HEADER), UTF_8, TEST_ENTRIES);
+		let path = std::env::temp_dir().join("try_clone_reader.mdx");
+		let mdict = MDictBuilder::from_reader(io::Cursor::new(data.clone()), &path)
+			.build()
+			.unwrap();
+		assert!(matches!(mdict.try_clone(), Err(Error::NoFilePath)));
+		let mdict = MDictBuilder::from_bytes(data).build().unwrap();
+		assert!(matches!(mdict.try_clone(), Err(Error::NoFilePath)));
+	}
+
+	#[test]
+	fn index_write_failure()
+	{
+		let data = v1_mdx(&utf16_header(TEST_HEADER), UTF_8, TEST_ENTRIES);
+		let path = std::env::temp_dir().join("index_write_failure.mdx");
+		std::fs::write(&path, &data).unwrap();
+		let mut mdict = MDictBuilder::new(&path)
+			.index_file("/nonexistent/dir/x.idx")
+			.build()
+			.unwrap();
+		assert_eq!(mdict.lookup("dog").unwrap()[0].definition, "<b>dog</b>");
+		std::fs::remove_file(&path).unwrap();
+		// no dictionary file to compare the index with
+		let mut mdict = MDictBuilder::from_reader(io::Cursor::new(data), &path)
+			.index_file(std::env::temp_dir().join("index_write_failure.idx"))
+			.build()
+			.unwrap();
+		assert_eq!(mdict.lookup("dog").unwrap()[0].definition, "<b>dog</b>");
+	}
+
+	#[test]
+	fn record_cache()
+	{
+		use std::num::NonZeroUsize;
+		use crate::cache::RecordCache;
+
+		assert!(RecordCache::new(0, None).is_none());
+		assert!(RecordCache::new(2, Some(0)).is_none());
+		assert!(RecordCache::new(0, Some(1 << 20)).is_none());
+		let data = v1_mdx(&utf16_header(TEST_HEADER), UTF_8, TEST_ENTRIES);
+		let mut mdict = MDictBuilder::from_bytes(data)
+			.cache_definition(false)
+			.cache_max_bytes(1 << 20)
+			.build()
+			.unwrap();
+		mdict.lookup("apple").unwrap();
+		assert_eq!(mdict.stats().cached_record_blocks, 0);
+
+		// by count
+		let mut cache = RecordCache::new(2, None).unwrap();
+		cache.put(0, vec![0; 4]);
+		cache.put(1, vec![0; 4]);
+		cache.put(2, vec![0; 4]);
+		assert_eq!(cache.len(), 2);
+		assert!(!cache.contains(&0));
+		assert_eq!(cache.bytes(), 8);
+		// replacing a key counts the new block only
+		cache.put(1, vec![0; 1]);
+		assert_eq!((cache.len(), cache.bytes()), (2, 5));
+		cache.reserve(NonZeroUsize::new(3).unwrap());
+		cache.put(3, vec![0; 1]);
+		assert_eq!(cache.len(), 3);
+		let mut empty = cache.empty_clone();
+		assert_eq!((empty.len(), empty.bytes()), (0, 0));
+		for key in 0..4 {
+			empty.put(key, vec![0]);
+		}
+		assert_eq!(empty.len(), 3);
+		cache.clear();
+		assert_eq!((cache.len(), cache.bytes()), (0, 0));
+		cache.put(4, vec![0; 2]);
+		assert_eq!((cache.len(), cache.bytes()), (1, 2));
+
+		// by bytes, block count is not limited
+		let mut cache = RecordCache::new(1, Some(10)).unwrap();
+		cache.put(0, vec![0; 4]);
+		cache.put(1, vec![0; 4]);
+		assert_eq!(cache.len(), 2);
+		cache.put(2, vec![0; 4]);
+		assert_eq!((cache.len(), cache.bytes()), (2, 8));
+		assert!(!cache.contains(&0));
+		// an oversized block is kept alone
+		cache.put(3, vec![0; 20]);
+		assert_eq!((cache.len(), cache.bytes()), (1, 20));
+		cache.put(4, vec![0; 4]);
+		assert_eq!((cache.len(), cache.bytes()), (1, 4));
+		assert!(cache.contains(&4));
+		let empty = cache.empty_clone();
+		assert_eq!((empty.len(), empty.bytes()), (0, 0));
+		cache.clear();
+		assert_eq!((cache.len(), cache.bytes()), (0, 0));
+	}
+
+	#[test]
+	fn corrupt_key_block_size()
+	{
+		let header = utf16_header(TEST_HEADER);
+		let data = v1_mdx(&header, UTF_8, TEST_ENTRIES);
+		// compressed size of the key block, after header, key block header,
+		// entry count and first/last keys in key block info
+		let size = 4 + header.len() + 4 + 16 + 4 + 1 + 5 + 1 + 3;
+		assert_eq!(data[size..size + 4], 34u32.to_be_bytes());
+		for skip in [false, true] {
+			let mut data = data.clone();
+			data[size..size + 4].copy_from_slice(&1000u32.to_be_bytes());
+			let result = MDictBuilder::from_bytes(data)
+				.skip_checksum_verification(skip)
+				.build();
+			assert!(result.is_err());
+		}
+	}
+
+	#[test]
+	fn lying_decompressed_size()
+	{
+		let mut data = v1_mdx(&utf16_header(TEST_HEADER), UTF_8, TEST_ENTRIES);
+		// record block infos precede the blocks, each block is 8 bytes of
+		// header then the record
+		let blocks: usize = TEST_ENTRIES.iter().map(|(_, definition)| definition.len() + 9).sum();
+		let info = data.len() - blocks - TEST_ENTRIES.len() * 8;
+		let size = info + 4;
+		assert_eq!(data[size..size + 4], 13u32.to_be_bytes());
+		data[size..size + 4].copy_from_slice(&111u32.to_be_bytes());
+		for cache in [false, true] {
+			let mut mdict = MDictBuilder::from_bytes(data.clone())
+				.cache_definition(cache)
+				.build()
+				.unwrap();
+			assert!(mdict.lookup("apple").is_err());
+			assert!(mdict.entries().next().unwrap().is_err());
+			assert!(mdict.lookup_batch(&["apple"]).is_err());
+		}
+	}
+
+	#[test]
+	fn huge_compressed_size()
+	{
+		let mut data = v1_mdx(&utf16_header(TEST_HEADER), UTF_8, TEST_ENTRIES);
+		let blocks: usize = TEST_ENTRIES.iter().map(|(_, definition)| definition.len() + 9).sum();
+		// compressed size of the last record block
+		let size = data.len() - blocks - 8;
+		assert_eq!(data[size..size + 4], 19u32.to_be_bytes());
+		data[size..size + 4].copy_from_slice(&0xffff_fff0u32.to_be_bytes());
+		let mut mdict = MDictBuilder::from_bytes(data)
+			.max_block_size(1024)
+			.build()
+			.unwrap();
+		assert!(matches!(mdict.lookup("zoo"), Err(Error::BlockTooLarge(0xffff_fff0))));
+		assert_eq!(mdict.lookup("dog").unwrap()[0].definition, "<b>dog</b>");
+		let report = mdict.verify().unwrap();
+		assert_eq!(report.failed_blocks, [2]);
+	}
+
+	#[test]
+	fn verify_bad_methods()
+	{
+		let mut data = v1_mdx(&utf16_header(TEST_HEADER), UTF_8, TEST_ENTRIES);
+		let blocks: usize = TEST_ENTRIES.iter().map(|(_, definition)| definition.len() + 9).sum();
+		let dog = data.len() - blocks + TEST_ENTRIES[0].1.len() + 9;
+		let zoo = dog + TEST_ENTRIES[1].1.len() + 9;
+		// unknown compress method, then unknown encryption method
+		data[dog] = 9;
+		data[zoo] = 0x70;
+		let mut mdict = MDictBuilder::from_bytes(data).build().unwrap();
+		assert!(matches!(mdict.lookup("dog").unwrap_err().root(), Error::InvalidCompressMethod(9)));
+		assert!(matches!(mdict.lookup("zoo").unwrap_err().root(), Error::InvalidEncryptMethod(7)));
+		let report = mdict.verify().unwrap();
+		assert_eq!(report.blocks_checked, 3);
+		assert_eq!(report.failed_blocks, [1, 2]);
+	}
+
+	#[test]
+	fn validate_corrupted_block()
+	{
+		let mut data = v1_mdx(&utf16_header(TEST_HEADER), UTF_8, TEST_ENTRIES);
+		let report = MDictBuilder::from_bytes(data.clone()).validate().unwrap();
+		assert!(report.is_valid());
+		assert_eq!(report.key_blocks_checked, 1);
+		assert_eq!(report.record_blocks_checked, 3);
+
+		let blocks: usize = TEST_ENTRIES.iter().map(|(_, definition)| definition.len() + 9).sum();
+		let dog = data.len() - blocks + TEST_ENTRIES[0].1.len() + 9;
+		data[dog + 8] = b'[';
+		// checksums are checked even when skipped for loading
+		for skip in [false, true] {
+			let report = MDictBuilder::from_bytes(data.clone())
+				.skip_checksum_verification(skip)
+				.validate()
+				.unwrap();
+			assert!(!report.is_valid());
+			assert!(report.failed_key_blocks.is_empty());
+			assert_eq!(report.record_blocks_checked, 3);
+			assert_eq!(report.failed_record_blocks, [1]);
+		}
+	}
+
+	#[test]
+	fn checksum_type_per_block()
+	{
+		let mut data = v1_mdx(&utf16_header(TEST_HEADER), UTF_8, TEST_ENTRIES);
+		// only the last record block is marked crc32
+		let record = format!("{}\0", TEST_ENTRIES[2].1);
+		let block = data.len() - record.len() - 8;
+		data[block + 3] = 1;
+		data[block + 4..block + 8].copy_from_slice(&crc32fast::hash(record.as_bytes()).to_be_bytes());
+		let mut mdict = MDictBuilder::from_bytes(data).build().unwrap();
+		assert_eq!(mdict.lookup("apple").unwrap()[0].definition, "<b>apple</b>");
+		assert_eq!(mdict.lookup("zoo").unwrap()[0].definition, "<b>zoo</b>");
+		assert!(mdict.verify().unwrap().failed_blocks.is_empty());
+	}
+
+	#[test]
+	fn preload_byte_limit()
+	{
+		let data = v1_mdx(&utf16_header(TEST_HEADER), UTF_8, TEST_ENTRIES);
+		let mut mdict = MDictBuilder::from_bytes(data)
+			.cache_definition(true)
+			.cache_max_bytes(16)
+			.build()
+			.unwrap();
+		mdict.preload().unwrap();
+		assert_eq!(mdict.stats().cached_record_blocks, 3);
+		*crate::mdx::reader_mut(&mut mdict.mdx.reader) = Box::new(io::Cursor::new(vec![]));
+		for (key, definition) in TEST_ENTRIES {
+			assert_eq!(mdict.lookup(key).unwrap()[0].definition, *definition);
+		}
+	}
+
+	#[test]
+	fn verify_ignores_skipped_checksum()
+	{
+		let mut data = v1_mdx(&utf16_header(TEST_HEADER), UTF_8, TEST_ENTRIES);
+		// flip the checksum of the last record block
+		let record = data.len() - TEST_ENTRIES[2].1.len() - 1;
+		data[record - 1] ^= 0xff;
+		for skip in [false, true] {
+			let mut mdict = MDictBuilder::from_bytes(data.clone())
+				.skip_checksum_verification(skip)
+				.build()
+				.unwrap();
+			let report = mdict.verify().unwrap();
+			assert_eq!(report.blocks_checked, 3);
+			assert_eq!(report.failed_blocks, [2]);
+		}
+	}
+
+	#[test]
+	fn definition_dedup()
+	{
+		use std::collections::HashSet;
+		use crate::{OwnedWordDefinition, WordDefinition};
+
+		let word = |key, definition: &str| WordDefinition { key, definition: definition.to_owned() };
+		assert_eq!(word("cat", "feline"), word("cat", "feline"));
+		assert_ne!(word("cat", "feline"), word("cat", "kitty"));
+
+		let mut set = HashSet::new();
+		assert!(set.insert(OwnedWordDefinition::from(word("cat", "feline"))));
+		assert!(!set.insert(OwnedWordDefinition::from(word("cat", "kitty"))));
+		assert!(set.insert(OwnedWordDefinition::from(word("dog", "canine"))));
+		assert_eq!(set.len(), 2);
+		assert_eq!(set.get(&word("cat", "").to_owned()).unwrap().definition, "feline");
+	}
+
+	#[test]
+	fn find_key_entry()
+	{
+		use crate::mdx::KeyEntry;
+		use crate::parser::find_entry;
+
+		let entries: Vec<_> = [(0, "apple"), (10, "band"), (20, "band"), (30, "cat")]
+			.into_iter()
+			.map(|(offset, text)| KeyEntry { offset, end: None, text: text.to_owned() })
+			.collect();
+		assert_eq!(find_entry(&entries, "apple").map(|entry| entry.offset), Some(0));
+		assert_eq!(find_entry(&entries, "band").map(|entry| entry.offset), Some(10));
+		assert_eq!(find_entry(&entries, "cat").map(|entry| entry.offset), Some(30));
+		assert!(find_entry(&entries, "ban").is_none());
+		assert!(find_entry(&entries, "dog").is_none());
+	}
+
+	#[test]
+	fn key_maker_chain()
+	{
+		let chain = KeyMakerChain::new(vec![
+			Box::new(|key: &Cow<str>, _: bool| fold_key(key)),
+			Box::new(|key: &Cow<str>, _: bool| key.replace('-', "")),
+		]);
+		assert_eq!(chain.make(&Cow::Borrowed("Ｅ-Mail"), false), "email");
+		assert_eq!(KeyMakerChain::default().make(&Cow::Borrowed("As Is"), false), "As Is");
+	}
+
+	#[test]
+	fn html_wrapper()
+	{
+		assert_eq!(strip_html_wrapper("<HTML><body class=\"x\"><b>a</b></body></html>\r\n"), "<b>a</b>");
+		assert_eq!(strip_html_wrapper("<b>a</b>"), "<b>a</b>");
+		assert_eq!(strip_html_wrapper("<bodyx>a</body>"), "<bodyx>a</body>");
+	}
+
+	#[test]
+	fn plain_text()
+	{
+		assert_eq!(html_to_text("<font color=\"red\">red</font><br>line<p>para</p>"),
+			"red\nline\n\npara");
+		assert_eq!(html_to_text("<img src=\"a>b.png\"/>&lt;tag&gt; &amp; &#233;&#x4e2d;&bogus;"),
+			"<tag> & é中&bogus;");
+		assert_eq!(html_to_text("<style>b { color: red }</style>1 < 2"), "1 < 2");
+		// unclosed tag eats the rest without splitting chars
+		assert_eq!(html_to_text("a<b中"), "a");
+		let definition = WordDefinition { key: "a", definition: "<a中".into() };
+		assert_eq!(definition.to_string(), "");
+		let definition = WordDefinition { key: "a", definition: "<b>a</b>&amp;中".into() };
+		assert_eq!(definition.to_owned().to_string(), definition.to_string());
+	}
+
+	#[test]
+	fn resource_separator()
+	{
+		assert_eq!(resource_path("\\img\\a.png"), "\\img\\a.png");
+		assert_eq!(resource_path("/img/a.png"), "\\img\\a.png");
+		assert_eq!(resource_path("img/a.png"), "\\img\\a.png");
+	}
+
+	#[test]
+	fn error_source()
+	{
+		let err = Error::from(io::Error::new(io::ErrorKind::UnexpectedEof, "eof"));
+		assert_eq!(err.to_string(), "Failed to read: eof");
+		assert!(err.source().is_some());
+	}
+
+	#[test]
+	fn wildcard()
+	{
+		assert!(wildcard_match("comp?te", "compute"));
+		assert!(!wildcard_match("comp?te", "compete1"));
+		assert!(wildcard_match("*tion", "nation"));
+		assert!(wildcard_match("a*b*c", "aXbYbc"));
+		assert!(wildcard_match("*", ""));
+		assert!(!wildcard_match("?", ""));
+		assert!(!wildcard_match("a*b", "acd"));
+	}
+
+	#[test]
+	fn levenshtein()
+	{
+		assert_eq!(edit_distance("kitten", "sitting"), 3);
+		assert_eq!(edit_distance("", "abc"), 3);
+		assert_eq!(edit_distance("abc", "abc"), 0);
+		assert_eq!(edit_distance("recieve", "receive"), 2);
+		assert_eq!(edit_distance("über", "uber"), 1);
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn non_utf8_file_stem()
+	{
+		use std::ffi::OsStr;
+		use std::os::unix::ffi::OsStrExt;
+		use std::path::Path;
+		use crate::Error;
+
+		let path = Path::new("/tmp").join(OsStr::from_bytes(b"\xff\xfe.mdx"));
+		let result = MDictBuilder::new(path).build();
+		assert!(matches!(result, Err(Error::NonUtf8FileStem(_))));
+	}
+
 	#[test]
 	fn lookup()
 	{
 		let mut mdx = MDictBuilder::new(MDX_V2).build().unwrap();
+		assert!(!mdx.contains("將進酒"));
+		assert!(mdx.contains("無"));
 		let definition = mdx.lookup("將進酒").unwrap();
-		assert!(definition.is_none());
+		assert!(definition.is_empty());
 		let definition = mdx.lookup("无").unwrap();
-		assert!(definition.is_some());
+		assert!(!definition.is_empty());
 		let definition = mdx.lookup("無").unwrap();
+		assert!(!definition.is_empty());
+		let definition = mdx.get_resource_raw("\\ZhongHuaSongPlane02b-HZ.woff").unwrap();
 		assert!(definition.is_some());
-		let definition = mdx.get_resource("\\ZhongHuaSongPlane02b-HZ.woff").unwrap();
+		let definition = mdx.get_resource_raw("/ZhongHuaSongPlane02b-HZ.woff").unwrap();
 		assert!(definition.is_some());
 	}
 
@@ -39,12 +980,39 @@ mod tests {
 			.build_with_key_maker(|key: &Cow<str>, _| key.to_ascii_lowercase())
 			.unwrap();
 		let definition = mdx.lookup("將進酒").unwrap();
-		assert!(definition.is_none());
+		assert!(definition.is_empty());
 		let definition = mdx.lookup("无").unwrap();
-		assert!(definition.is_some());
+		assert!(!definition.is_empty());
 		let definition = mdx.lookup("無").unwrap();
+		assert!(!definition.is_empty());
+		let definition = mdx.get_resource_raw("\\ZhongHuaSongPlane02b-HZ.woff").unwrap();
 		assert!(definition.is_some());
-		let definition = mdx.get_resource("\\ZhongHuaSongPlane02b-HZ.woff").unwrap();
-		assert!(definition.is_some());
+	}
+
+	#[test]
+	fn lookup_prefix()
+	{
+		let mut mdx = MDictBuilder::new(MDX_V2).build().unwrap();
+		let definitions = mdx.lookup_prefix("無", true).unwrap();
+		assert!(!definitions.is_empty());
+		assert!(definitions.iter().all(|definition| definition.key.starts_with("無")));
+		let count = definitions.len();
+		let without_redirects = mdx.lookup_prefix("無", false).unwrap();
+		assert!(without_redirects.len() <= count);
+	}
+
+	#[test]
+	fn lookup_first_last()
+	{
+		let data = v1_mdx(&utf16_header(TEST_HEADER), UTF_8, TEST_ENTRIES);
+		let mut mdx = MDictBuilder::from_bytes(data).build().unwrap();
+		assert_eq!(mdx.keys().next(), Some("apple"));
+		assert_eq!(mdx.keys().last(), Some("zoo"));
+		assert!(mdx.contains("apple"));
+		assert!(mdx.contains("zoo"));
+		assert_eq!(mdx.lookup("apple").unwrap()[0].definition, "<b>apple</b>");
+		assert_eq!(mdx.lookup("zoo").unwrap()[0].definition, "<b>zoo</b>");
+		assert_eq!(mdx.first_entry().unwrap().unwrap().key, "apple");
+		assert_eq!(mdx.last_entry().unwrap().unwrap().key, "zoo");
 	}
 }
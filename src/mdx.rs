@@ -1,13 +1,43 @@
 use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
 use std::fs::File;
-use std::io::BufReader;
-use std::path::PathBuf;
-use encoding_rs::{Encoding, UTF_16LE};
-use crate::parser::{decode_slice_string, load, lookup_record};
+use std::io::{BufReader, Cursor, Read, Seek, Write};
+use std::ops::Index;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use aho_corasick::AhoCorasick;
+use caseless::default_case_fold_str;
+use encoding_rs::{Encoding, UTF_8};
+use regex::Regex;
+use unicode_normalization::UnicodeNormalization;
+use crate::index::{index_fingerprint, read_index, write_index};
+use crate::parser::{decode_definition, edit_distance, entry_record, find_entry, html_to_text, key_range, Limits, load, load_indexed, lookup_record, prefix_range, preload_records, read_record, record_offset, resource_path, strip_html_wrapper, validate, verify_records, Version, wildcard_match};
 use crate::{Error, Result};
+use crate::cache::RecordCache;
+use crate::normalize::{KeyMakerChain, strip_whitespace};
+#[cfg(feature = "async")]
+use crate::AsyncMDict;
 
-pub type Reader = BufReader<File>;
+/// any seekable source a dictionary could be parsed from, files,
+/// memory or others given to MDictBuilder::from_reader
+pub(crate) trait ReadSeek: Read + Seek + Send {}
+
+impl<T> ReadSeek for T where T: Read + Seek + Send {}
+
+/// reader of a dictionary, boxed so MDict is not generic over it
+pub(crate) type Reader = Box<dyn ReadSeek>;
+
+/// reader of a dictionary we have exclusive access to, no locking needed
+#[inline]
+pub(crate) fn reader_mut(reader: &mut Mutex<Reader>) -> &mut Reader
+{
+	reader.get_mut().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// definitions starting with this are redirects to another headword
+const LINK_PREFIX: &str = "@@@LINK=";
 
 pub trait KeyMaker {
 	fn make(&self, key: &Cow<str>, resource: bool) -> String;
@@ -21,66 +51,730 @@ impl<F> KeyMaker for F where F: Fn(&Cow<str>, bool) -> String {
 	}
 }
 
+/// key maker used by MDictBuilder::build, NFC normalized (NFKC with
+/// cjk_normalize) then Unicode case folded (ascii lowercased with
+/// ascii_only_case_fold), or kept as is with case_sensitive, whitespace
+/// and zero-width characters of headwords dropped first with
+/// ignore_whitespace
+#[derive(Debug, Clone)]
+pub struct DefaultKeyMaker {
+	normalize: bool,
+	cjk: bool,
+	case_sensitive: bool,
+	ignore_whitespace: bool,
+	ascii_fold: bool,
+}
+
+impl KeyMaker for DefaultKeyMaker {
+	fn make(&self, key: &Cow<str>, resource: bool) -> String
+	{
+		let key = if self.ignore_whitespace && !resource {
+			strip_whitespace(key)
+		} else {
+			Cow::Borrowed(key.as_ref())
+		};
+		if self.case_sensitive {
+			return key.to_string();
+		}
+		let key = if self.cjk {
+			Cow::Owned(key.nfkc().collect::<String>())
+		} else if self.normalize {
+			Cow::Owned(key.nfc().collect::<String>())
+		} else {
+			key
+		};
+		if self.ascii_fold {
+			key.to_ascii_lowercase()
+		} else {
+			default_case_fold_str(&key)
+		}
+	}
+}
+
+/// Send and Sync as long as the key maker is. Lookups take `&mut self`,
+/// so share it between threads behind a `Mutex` or `RwLock` write lock,
+/// or make one instance per thread with try_clone. Readers are kept in a
+/// `Mutex` only to make this Sync, they are accessed by `get_mut` without
+/// ever locking.
 pub struct MDict<M: KeyMaker> {
 	pub(crate) mdx: Mdx,
+	/// dictionaries added by MDictBuilder::add_mdx, queried by lookup and contains only
+	pub(crate) merged: Vec<Mdx>,
 	pub(crate) resources: Vec<Mdx>,
 	pub(crate) key_maker: M,
+	/// `@@@LINK=` redirects followed by cross_reference_lookup at most
+	pub(crate) max_redirect_depth: usize,
 }
 
 pub struct Mdx {
 	pub(crate) encoding: &'static Encoding,
-	pub(crate) title: String,
-	#[allow(unused)]
+	pub(crate) info: DictInfo,
+	pub(crate) version: Version,
 	pub(crate) encrypted: u8,
-	pub(crate) key_entries: Vec<KeyEntry>,
-	pub(crate) records_info: Vec<BlockEntryInfo>,
-	pub(crate) reader: Reader,
+	pub(crate) entry_count: usize,
+	pub(crate) key_blocks: Arc<Vec<KeyBlockRange>>,
+	pub(crate) key_entries: Arc<Vec<KeyEntry>>,
+	pub(crate) records_info: Arc<Vec<BlockEntryInfo>>,
+	/// only accessed by get_mut, the lock is for Sync alone
+	pub(crate) reader: Mutex<Reader>,
 	pub(crate) record_block_offset: u64,
-	pub(crate) record_cache: Option<HashMap<usize, Vec<u8>>>,
+	pub(crate) limits: Limits,
+	pub(crate) encoding_override: Option<&'static Encoding>,
+	/// resource dictionary (.mdd)
+	pub(crate) resource: bool,
+	/// decoded record blocks by buf offset
+	pub(crate) record_cache: Option<RecordCache>,
+	/// file loaded from, None for readers
+	pub(crate) path: Option<PathBuf>,
 }
 
-#[derive(Debug)]
+/// dictionary metadata from the header
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DictInfo {
+	pub title: String,
+	pub description: String,
+	pub author: String,
+	pub creation_date: String,
+	pub encoding: String,
+	/// engine version generated the dictionary
+	pub version: String,
+}
+
+#[derive(Debug, Clone)]
 pub(crate) struct KeyEntry {
 	pub(crate) offset: usize,
+	/// offset of next record, None for the last one
+	pub(crate) end: Option<usize>,
 	pub(crate) text: String,
 }
 
-#[derive(Debug)]
+/// checksum of decompressed block data, marked in each block header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ChecksumType {
+	Adler32,
+	Crc32,
+}
+
+#[derive(Debug, Clone)]
 pub(crate) struct BlockEntryInfo {
 	pub(crate) compressed_size: usize,
 	pub(crate) decompressed_size: usize,
+	/// number of keys in a key block, 0 for record blocks
+	pub(crate) entry_count: usize,
 }
 
 #[derive(Debug)]
 pub(crate) struct RecordOffset {
 	pub(crate) buf_offset: usize,
 	pub(crate) block_offset: usize,
+	/// end of the entry in decompressed block
+	pub(crate) entry_end: usize,
 	pub(crate) record_size: usize,
 	pub(crate) decomp_size: usize,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct WordDefinition<'a> {
 	pub key: &'a str,
 	pub definition: String,
 }
 
+/// stage of loading reported by MDictBuilder::build_with_progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadProgress {
+	ReadingHeader,
+	/// key blocks decoded so far
+	ReadingKeyBlocks {
+		done: usize,
+		total: usize,
+	},
+	ReadingRecordIndex,
+	/// everything loaded, reported once
+	Done,
+}
+
+/// which neighbour lookup_near takes when there is no exact match
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Nearest {
+	/// the last key less than the word
+	Before,
+	/// the first key greater than the word
+	After,
+}
+
+/// statistics of the dictionary, see MDict::stats
+#[derive(Debug, Clone)]
+pub struct DictStats {
+	pub total_key_blocks: usize,
+	pub total_entries: usize,
+	pub total_record_blocks: usize,
+	pub cached_record_blocks: usize,
+	/// decompressed bytes of cached record blocks
+	pub cached_bytes: usize,
+	/// file offset where record blocks start
+	pub record_block_offset: u64,
+}
+
+/// span of a key block, keys are as stored in the file
+#[derive(Debug, Clone)]
+pub struct KeyBlockRange {
+	pub first_key: String,
+	pub last_key: String,
+	pub entry_count: usize,
+}
+
+/// result of MDictBuilder::validate
+#[derive(Debug, Clone, Default)]
+pub struct ValidationReport {
+	pub key_blocks_checked: usize,
+	/// indices of key blocks failed to decode or checksum
+	pub failed_key_blocks: Vec<usize>,
+	pub record_blocks_checked: usize,
+	/// indices of record blocks failed to decode or checksum
+	pub failed_record_blocks: Vec<usize>,
+}
+
+impl ValidationReport {
+	/// no block failed
+	#[inline]
+	pub fn is_valid(&self) -> bool
+	{
+		self.failed_key_blocks.is_empty() && self.failed_record_blocks.is_empty()
+	}
+}
+
+/// result of MDict::verify
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+	pub blocks_checked: usize,
+	pub blocks_failed: usize,
+	/// indices of record blocks failed to decode or checksum
+	pub failed_blocks: Vec<usize>,
+}
+
+impl WordDefinition<'_> {
+	/// copy into a definition not borrowing anything
+	#[inline]
+	pub fn to_owned(&self) -> OwnedWordDefinition
+	{
+		OwnedWordDefinition {
+			key: self.key.to_owned(),
+			definition: self.definition.clone(),
+		}
+	}
+}
+
+/// WordDefinition not borrowing anything, for storing or sending around
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OwnedWordDefinition {
+	pub key: String,
+	pub definition: String,
+}
+
+/// equal by key only, so a set keeps one definition for each key
+impl PartialEq for OwnedWordDefinition {
+	#[inline]
+	fn eq(&self, other: &Self) -> bool
+	{
+		self.key == other.key
+	}
+}
+
+impl Eq for OwnedWordDefinition {}
+
+impl Hash for OwnedWordDefinition {
+	#[inline]
+	fn hash<H: Hasher>(&self, state: &mut H)
+	{
+		self.key.hash(state);
+	}
+}
+
+/// plain text of the definition, html tags stripped and common
+/// entities decoded
+impl Display for WordDefinition<'_> {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result
+	{
+		f.write_str(&html_to_text(&self.definition))
+	}
+}
+
+/// same plain text as WordDefinition
+impl Display for OwnedWordDefinition {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result
+	{
+		f.write_str(&html_to_text(&self.definition))
+	}
+}
+
+impl From<WordDefinition<'_>> for OwnedWordDefinition {
+	#[inline]
+	fn from(value: WordDefinition<'_>) -> Self
+	{
+		OwnedWordDefinition {
+			key: value.key.to_owned(),
+			definition: value.definition,
+		}
+	}
+}
+
 impl<M: KeyMaker> MDict<M> {
-	pub fn lookup<'a>(&mut self, word: &'a str) -> Result<Option<WordDefinition<'a>>>
+	/// lookup word in every merged dictionary, one definition for each
+	/// dictionary having it, in the order dictionaries were added
+	pub fn lookup<'a>(&mut self, word: &'a str) -> Result<Vec<WordDefinition<'a>>>
+	{
+		let key = self.key_maker.make(&Cow::Borrowed(word), false);
+		let mut definitions = vec![];
+		for mdx in std::iter::once(&mut self.mdx).chain(&mut self.merged) {
+			let encoding = mdx.encoding;
+			if let Some(slice) = lookup_record(mdx, &key)? {
+				let definition = decode_definition(&slice, encoding)?;
+				definitions.push(WordDefinition { key: word, definition });
+			}
+		}
+		Ok(definitions)
+	}
+
+	/// lookup word with all whitespace and zero-width characters dropped,
+	/// the first definition found in merged dictionaries. Keys only match
+	/// if loaded with MDictBuilder::ignore_whitespace or a key maker
+	/// dropping them too.
+	pub fn lookup_ignore_whitespace<'a>(&mut self, word: &'a str)
+		-> Result<Option<WordDefinition<'a>>>
+	{
+		let key = strip_whitespace(word);
+		let definition = self.lookup(&key)?
+			.into_iter()
+			.next()
+			.map(|found| WordDefinition { key: word, definition: found.definition });
+		Ok(definition)
+	}
+
+	/// check if any merged dictionary has the word,
+	/// by searching keys only, record blocks are not touched
+	pub fn contains(&self, word: &str) -> bool
+	{
+		let key = self.key_maker.make(&Cow::Borrowed(word), false);
+		std::iter::once(&self.mdx)
+			.chain(&self.merged)
+			.any(|mdx| find_entry(&mdx.key_entries, &key).is_some())
+	}
+
+	/// lookup word, or the nearest key in sorted order if not found,
+	/// the key of result is as made by the key maker
+	pub fn lookup_near(&mut self, word: &str, nearest: Nearest)
+		-> Result<Option<WordDefinition<'_>>>
 	{
-		let encoding = self.mdx.encoding;
 		let key = self.key_maker.make(&Cow::Borrowed(word), false);
-		if let Some(slice) = lookup_record(&mut self.mdx, &key)? {
-			let definition = decode_slice_string(&slice, encoding)?.0.to_string();
-			Ok(Some(WordDefinition { key: word, definition }))
+		let entries = &self.mdx.key_entries;
+		let range = key_range(entries, &key);
+		let idx = if !range.is_empty() {
+			Some(range.start)
 		} else {
-			Ok(None)
+			match nearest {
+				Nearest::Before => range.start.checked_sub(1),
+				Nearest::After => (range.start < entries.len()).then_some(range.start),
+			}
+		};
+		Ok(self.entry_definitions(idx)?.pop())
+	}
+
+	/// entry with the smallest key, the key is as made by the key maker
+	#[inline]
+	pub fn first_entry(&mut self) -> Result<Option<WordDefinition<'_>>>
+	{
+		let first = (!self.mdx.key_entries.is_empty()).then_some(0);
+		Ok(self.entry_definitions(first)?.pop())
+	}
+
+	/// entry with the largest key, the key is as made by the key maker
+	#[inline]
+	pub fn last_entry(&mut self) -> Result<Option<WordDefinition<'_>>>
+	{
+		let last = self.mdx.key_entries.len().checked_sub(1);
+		Ok(self.entry_definitions(last)?.pop())
+	}
+
+	/// lookup many words at once, results are in the order of words.
+	/// Records are read in file order so each record block is decoded once.
+	pub fn lookup_batch<'a>(&mut self, words: &'a [&str])
+		-> Result<Vec<Option<WordDefinition<'a>>>>
+	{
+		let (entries, mut records) = BlockReader::split(&mut self.mdx);
+		let mut found: Vec<(usize, &KeyEntry)> = words
+			.iter()
+			.enumerate()
+			.filter_map(|(i, word)| {
+				let key = self.key_maker.make(&Cow::Borrowed(word), false);
+				let range = key_range(entries, &key);
+				(!range.is_empty()).then(|| (i, &entries[range.start]))
+			})
+			.collect();
+		found.sort_by_key(|(_, entry)| entry.offset);
+
+		let mut definitions: Vec<Option<WordDefinition<'a>>> = words.iter().map(|_| None).collect();
+		for (i, entry) in found {
+			let definition = records.definition(entry)?;
+			definitions[i] = Some(WordDefinition { key: words[i], definition });
+		}
+		Ok(definitions)
+	}
+
+	/// lookup a word list like lookup_batch, words making the same key
+	/// are looked up once and share the definition
+	pub fn lookup_word_list<'a>(&mut self, words: &'a [&str])
+		-> Result<Vec<Option<WordDefinition<'a>>>>
+	{
+		let mut unique: Vec<&str> = vec![];
+		let mut positions: HashMap<String, usize> = HashMap::new();
+		let slots: Vec<usize> = words
+			.iter()
+			.map(|word| {
+				let key = self.key_maker.make(&Cow::Borrowed(word), false);
+				*positions.entry(key).or_insert_with(|| {
+					unique.push(word);
+					unique.len() - 1
+				})
+			})
+			.collect();
+		let definitions: Vec<Option<String>> = self.lookup_batch(&unique)?
+			.into_iter()
+			.map(|found| found.map(|found| found.definition))
+			.collect();
+		Ok(words
+			.iter()
+			.zip(slots)
+			.map(|(word, slot)| definitions[slot]
+				.as_ref()
+				.map(|definition| WordDefinition { key: word, definition: definition.clone() }))
+			.collect())
+	}
+
+	/// lookup word following `@@@LINK=` redirects, at most as many times
+	/// as MDictBuilder::max_redirect_depth, Error::RedirectLoop if the
+	/// chain is longer. The last redirect is returned as is if its target
+	/// is missing, or the first one when following is disabled.
+	pub fn cross_reference_lookup<'a>(&mut self, word: &'a str)
+		-> Result<Option<WordDefinition<'a>>>
+	{
+		let encoding = self.mdx.encoding;
+		let mut key = self.key_maker.make(&Cow::Borrowed(word), false);
+		let mut redirect = None;
+		let mut depth = 0;
+		loop {
+			let Some(slice) = lookup_record(&mut self.mdx, &key)? else {
+				return Ok(redirect.map(|definition| WordDefinition { key: word, definition }));
+			};
+			let definition = decode_definition(&slice, encoding)?;
+			match definition.strip_prefix(LINK_PREFIX) {
+				Some(target) if self.max_redirect_depth > 0 => {
+					let target = target.trim();
+					if depth == self.max_redirect_depth {
+						return Err(Error::RedirectLoop { from: key, to: target.to_owned() });
+					}
+					key = self.key_maker.make(&Cow::Borrowed(target), false);
+					redirect = Some(definition);
+					depth += 1;
+				}
+				_ => return Ok(Some(WordDefinition { key: word, definition })),
+			}
+		}
+	}
+
+	/// find all entries with the same key, in file order
+	pub fn lookup_all<'a>(&mut self, word: &'a str) -> Result<Vec<WordDefinition<'a>>>
+	{
+		let encoding = self.mdx.encoding;
+		let key = self.key_maker.make(&Cow::Borrowed(word), false);
+		let mut definitions = vec![];
+		for idx in key_range(&self.mdx.key_entries, &key) {
+			if let Some(slice) = entry_record(&mut self.mdx, idx)? {
+				let definition = decode_definition(&slice, encoding)?;
+				definitions.push(WordDefinition { key: word, definition });
+			}
+		}
+		Ok(definitions)
+	}
+
+	/// find all entries with key starting with prefix,
+	/// redirect entries (@@@LINK=) are skipped unless include_redirects
+	pub fn lookup_prefix(&mut self, prefix: &str, include_redirects: bool)
+		-> Result<Vec<WordDefinition<'_>>>
+	{
+		let prefix = self.key_maker.make(&Cow::Borrowed(prefix), false);
+		let range = prefix_range(&self.mdx.key_entries, &prefix);
+		let mut definitions = self.entry_definitions(range)?;
+		if !include_redirects {
+			definitions.retain(|definition| !definition.definition.starts_with(LINK_PREFIX));
+		}
+		Ok(definitions)
+	}
+
+	/// find all entries with key between from and to, both inclusive,
+	/// empty if from is greater than to
+	pub fn scan_range(&mut self, from: &str, to: &str) -> Result<Vec<WordDefinition<'_>>>
+	{
+		let from = self.key_maker.make(&Cow::Borrowed(from), false);
+		let to = self.key_maker.make(&Cow::Borrowed(to), false);
+		if from > to {
+			return Ok(vec![]);
 		}
+		let entries = &self.mdx.key_entries;
+		let start = entries.partition_point(|entry| entry.text < from);
+		let end = entries.partition_point(|entry| entry.text <= to);
+		self.entry_definitions(start..end)
 	}
 
-	pub fn get_resource(&mut self, path: &str) -> Result<Option<Cow<[u8]>>>
+	/// find all entries matching pattern, `*` matches any sequence and `?`
+	/// matches a single char. Only entries sharing the literal prefix before
+	/// the first wildcard are scanned, so a pattern starting with a wildcard
+	/// scans the whole dictionary.
+	pub fn lookup_wildcard(&mut self, pattern: &str) -> Result<Vec<WordDefinition<'_>>>
 	{
-		let key = self.key_maker.make(&Cow::Borrowed(path), true);
+		let pattern = self.key_maker.make(&Cow::Borrowed(pattern), false);
+		let prefix = match pattern.find(['*', '?']) {
+			Some(idx) => &pattern[..idx],
+			None => &pattern,
+		};
+		let entries = &self.mdx.key_entries;
+		let matched: Vec<usize> = prefix_range(entries, prefix)
+			.filter(|idx| wildcard_match(&pattern, &entries[*idx].text))
+			.collect();
+		self.entry_definitions(matched)
+	}
+
+	/// find all entries whose key matches the regex, keys are matched as
+	/// made by the key maker (lowercased by default). Every key is tested,
+	/// so this is O(n) over the whole dictionary.
+	pub fn lookup_regex(&mut self, pattern: &str) -> Result<Vec<WordDefinition<'_>>>
+	{
+		let regex = Regex::new(pattern).map_err(Error::InvalidRegex)?;
+		let matched: Vec<usize> = self.mdx.key_entries
+			.iter()
+			.enumerate()
+			.filter(|(_, entry)| regex.is_match(&entry.text))
+			.map(|(idx, _)| idx)
+			.collect();
+		self.entry_definitions(matched)
+	}
+
+	/// find entries within max_distance edits (Levenshtein, in chars) of
+	/// word, for typo tolerance. Only entries sharing the first char of
+	/// word are compared, a typo there is not tolerated.
+	pub fn lookup_approximate(&mut self, word: &str, max_distance: usize)
+		-> Result<Vec<WordDefinition<'_>>>
+	{
+		let key = self.key_maker.make(&Cow::Borrowed(word), false);
+		let first = match key.chars().next() {
+			Some(c) => &key[..c.len_utf8()],
+			None => "",
+		};
+		let entries = &self.mdx.key_entries;
+		let matched: Vec<usize> = prefix_range(entries, first)
+			.filter(|idx| edit_distance(&key, &entries[*idx].text) <= max_distance)
+			.collect();
+		self.entry_definitions(matched)
+	}
+
+	/// find entries whose key contains any of the patterns, grouped by
+	/// pattern, all patterns are matched in a single pass over the keys.
+	/// Keys and patterns are compared as made by the key maker.
+	pub fn lookup_containing(&mut self, patterns: &[&str])
+		-> Result<HashMap<String, Vec<WordDefinition<'_>>>>
+	{
+		let keys: Vec<String> = patterns
+			.iter()
+			.map(|pattern| self.key_maker.make(&Cow::Borrowed(pattern), false))
+			.collect();
+		let searcher = AhoCorasick::new(&keys).or(Err(Error::InvalidData))?;
+		let mut matched: Vec<(usize, Vec<usize>)> = vec![];
+		for (idx, entry) in self.mdx.key_entries.iter().enumerate() {
+			let mut found: Vec<usize> = searcher
+				.find_overlapping_iter(&entry.text)
+				.map(|m| m.pattern().as_usize())
+				.collect();
+			if !found.is_empty() {
+				found.sort_unstable();
+				found.dedup();
+				matched.push((idx, found));
+			}
+		}
+		let encoding = self.mdx.encoding;
+		let mut decoded = vec![];
+		for (idx, found) in matched {
+			if let Some(slice) = entry_record(&mut self.mdx, idx)? {
+				decoded.push((idx, found, decode_definition(&slice, encoding)?));
+			}
+		}
+		let entries = &self.mdx.key_entries;
+		let mut result: HashMap<String, Vec<WordDefinition<'_>>> = HashMap::new();
+		for (idx, found, definition) in decoded {
+			for pattern in found {
+				result.entry(patterns[pattern].to_owned())
+					.or_default()
+					.push(WordDefinition {
+						key: &entries[idx].text,
+						definition: definition.clone(),
+					});
+			}
+		}
+		Ok(result)
+	}
+
+	/// decode definitions of entries by index, with keys borrowed from entries
+	fn entry_definitions(&mut self, indices: impl IntoIterator<Item=usize>)
+		-> Result<Vec<WordDefinition<'_>>>
+	{
+		let encoding = self.mdx.encoding;
+		let mut found = vec![];
+		for idx in indices {
+			if let Some(slice) = entry_record(&mut self.mdx, idx)? {
+				let definition = decode_definition(&slice, encoding)?;
+				found.push((idx, definition));
+			}
+		}
+		let entries = &self.mdx.key_entries;
+		Ok(found.into_iter()
+			.map(|(idx, definition)| WordDefinition {
+				key: &entries[idx].text,
+				definition,
+			})
+			.collect())
+	}
+
+	/// number of entries recorded in header
+	pub fn entry_count(&self) -> usize
+	{
+		self.mdx.entry_count
+	}
+
+	/// true if the header records no entries
+	#[inline]
+	pub fn is_empty(&self) -> bool
+	{
+		self.mdx.is_empty()
+	}
+
+	/// iterate all keys without touching record blocks
+	pub fn keys(&self) -> impl Iterator<Item=&str>
+	{
+		self.mdx.key_entries
+			.iter()
+			.map(|entry| entry.text.as_str())
+	}
+
+	/// iterate all entries in key order, record blocks are decoded lazily,
+	/// one at a time
+	#[inline]
+	pub fn entries(&mut self) -> impl Iterator<Item=Result<WordDefinition<'_>>>
+	{
+		self.all_entries()
+	}
+
+	#[inline]
+	fn all_entries(&mut self) -> Entries<'_>
+	{
+		Entries::new(&mut self.mdx, 0)
+	}
+
+	/// write all entries in key order as `key\tdefinition\n` lines, with
+	/// backslashes, tabs and line breaks escaped, returns entries written
+	pub fn export_to_tsv(&mut self, mut writer: impl Write) -> Result<usize>
+	{
+		fn escape(text: &str) -> Cow<'_, str>
+		{
+			if !text.contains(['\\', '\t', '\n', '\r']) {
+				return Cow::Borrowed(text);
+			}
+			let mut escaped = String::with_capacity(text.len() + 8);
+			for c in text.chars() {
+				match c {
+					'\\' => escaped.push_str("\\\\"),
+					'\t' => escaped.push_str("\\t"),
+					'\n' => escaped.push_str("\\n"),
+					'\r' => escaped.push_str("\\r"),
+					c => escaped.push(c),
+				}
+			}
+			Cow::Owned(escaped)
+		}
+
+		let mut count = 0;
+		for entry in self.entries() {
+			let entry = entry?;
+			writeln!(writer, "{}\t{}", escape(entry.key), escape(&entry.definition))
+				.map_err(Error::FailedWriting)?;
+			count += 1;
+		}
+		writer.flush().map_err(Error::FailedWriting)?;
+		Ok(count)
+	}
+
+	/// iterate entries in key order from the first key not less than start,
+	/// record blocks are decoded lazily like entries
+	pub fn scan_from(&mut self, start: &str) -> impl Iterator<Item=Result<WordDefinition<'_>>>
+	{
+		let start = self.key_maker.make(&Cow::Borrowed(start), false);
+		let first = key_range(&self.mdx.key_entries, &start).start;
+		Entries::new(&mut self.mdx, first)
+	}
+
+	/// iterate decompressed record blocks in file order, one at a time,
+	/// bypassing the cache
+	pub fn blocks_iter(&mut self) -> impl Iterator<Item=Result<Vec<u8>>> + '_
+	{
+		let mdx = &mut self.mdx;
+		RecordBlocks {
+			records_info: mdx.records_info.iter(),
+			reader: reader_mut(&mut mdx.reader),
+			record_block_offset: mdx.record_block_offset,
+			limits: mdx.limits,
+			buf_offset: 0,
+		}
+	}
+
+	/// raw bytes of the definition, without any decoding
+	pub fn lookup_raw(&mut self, word: &str) -> Result<Option<Cow<'_, [u8]>>>
+	{
+		let key = self.key_maker.make(&Cow::Borrowed(word), false);
+		lookup_record(&mut self.mdx, &key)
+	}
+
+	/// owned copy of lookup_raw, the full record up to the next entry
+	#[inline]
+	pub fn lookup_definition_bytes(&mut self, word: &str) -> Result<Option<Vec<u8>>>
+	{
+		Ok(self.lookup_raw(word)?.map(Cow::into_owned))
+	}
+
+	/// definition as html, with null terminator, BOM and the html/body
+	/// tags wrapping the whole definition stripped
+	pub fn lookup_html(&mut self, word: &str) -> Result<Option<String>>
+	{
+		let encoding = self.mdx.encoding;
+		let Some(raw) = self.lookup_raw(word)? else {
+			return Ok(None);
+		};
+		let (text, _) = encoding.decode_with_bom_removal(&raw);
+		let text = text.trim_end_matches('\0').trim_start_matches('\u{feff}');
+		Ok(Some(strip_html_wrapper(text).to_owned()))
+	}
+
+	#[deprecated(note = "use get_resource_raw, or get_resource_text for text")]
+	#[inline]
+	pub fn get_resource(&mut self, path: &str) -> Result<Option<Cow<'_, [u8]>>>
+	{
+		self.get_resource_raw(path)
+	}
+
+	/// find resource by path, both `/` and `\\` are accepted as separator,
+	/// the complete record as is, trailing null included if any
+	pub fn get_resource_raw(&mut self, path: &str) -> Result<Option<Cow<'_, [u8]>>>
+	{
+		let key = self.key_maker.make(&resource_path(path), true);
 		for mdx in &mut self.resources {
 			if let Some(slice) = lookup_record(mdx, &key)? {
 				return Ok(Some(slice));
@@ -89,28 +783,663 @@ impl<M: KeyMaker> MDict<M> {
 		Ok(None)
 	}
 
+	/// resource decoded as text, by its BOM or UTF-8 without one, trailing
+	/// nulls stripped. Encoding of resource files is for the paths only,
+	/// it is always UTF-16LE while resources are stored as they are.
+	pub fn get_resource_text(&mut self, path: &str) -> Result<Option<String>>
+	{
+		let Some(slice) = self.get_resource_raw(path)? else {
+			return Ok(None);
+		};
+		let (text, _, _) = UTF_8.decode(&slice);
+		Ok(Some(text.trim_end_matches('\0').to_owned()))
+	}
+
+	/// decode every record block and check its checksum,
+	/// decoded data is dropped right away
+	#[inline]
+	pub fn verify(&mut self) -> Result<VerifyReport>
+	{
+		verify_records(&mut self.mdx)
+	}
+
+	/// decode all record blocks of the dictionaries into cache, so lookups
+	/// never touch the files again. The definition cache is enabled and
+	/// grown as needed, a cache_max_bytes limit is lifted to hold every
+	/// block. Blocks already cached are skipped.
+	pub fn preload(&mut self) -> Result<()>
+	{
+		for mdx in std::iter::once(&mut self.mdx).chain(&mut self.merged) {
+			preload_records(mdx)?;
+		}
+		Ok(())
+	}
+
+	/// paths of all resources in loaded mdd files, as made by the key maker
+	pub fn resource_list(&self) -> Vec<String>
+	{
+		self.resources
+			.iter()
+			.flat_map(|mdd| mdd.key_entries.iter())
+			.map(|entry| entry.text.clone())
+			.collect()
+	}
+
+	/// memory needed to hold all record blocks decompressed, see preload
+	#[inline]
+	pub fn total_decompressed_size(&self) -> usize
+	{
+		self.mdx.total_decompressed_size()
+	}
+
+	/// size of all record blocks in the file
+	#[inline]
+	pub fn total_compressed_size(&self) -> usize
+	{
+		self.mdx.total_compressed_size()
+	}
+
+	/// total compressed size over total decompressed size of record blocks,
+	/// the lower it is the more a cached block saves, 1.0 when empty
+	pub fn compression_ratio(&self) -> f64
+	{
+		let decompressed = self.total_decompressed_size();
+		if decompressed == 0 {
+			return 1.0;
+		}
+		self.total_compressed_size() as f64 / decompressed as f64
+	}
+
+	/// parse all files of the dictionary again after they changed on disk,
+	/// caches are cleared. Nothing is replaced if any file fails to load.
+	/// Dictionaries built from a reader fail with Error::NoFilePath.
+	pub fn reload(&mut self) -> Result<()>
+	{
+		let key_maker = &self.key_maker;
+		let mdx = self.mdx.reloaded(key_maker)?;
+		let merged = self.merged
+			.iter()
+			.map(|mdx| mdx.reloaded(key_maker))
+			.collect::<Result<_>>()?;
+		let resources = self.resources
+			.iter()
+			.map(|mdd| mdd.reloaded(key_maker))
+			.collect::<Result<_>>()?;
+		self.mdx = mdx;
+		self.merged = merged;
+		self.resources = resources;
+		Ok(())
+	}
+
+	/// free cached record blocks of the dictionary and the merged ones,
+	/// resources are kept, see cache_clear_resource
+	pub fn cache_clear(&mut self)
+	{
+		self.mdx.cache_clear();
+		self.merged.iter_mut().for_each(Mdx::cache_clear);
+	}
+
+	/// free cached blocks of resource dictionaries (.mdd) only
+	pub fn cache_clear_resource(&mut self)
+	{
+		self.resources.iter_mut().for_each(Mdx::cache_clear);
+	}
+
+	/// parsing and cache statistics of the dictionary, no file access
+	pub fn stats(&self) -> DictStats
+	{
+		let mdx = &self.mdx;
+		let cache = mdx.record_cache.as_ref();
+		DictStats {
+			total_key_blocks: mdx.key_blocks.len(),
+			total_entries: mdx.entry_count,
+			total_record_blocks: mdx.records_info.len(),
+			cached_record_blocks: cache.map_or(0, RecordCache::len),
+			cached_bytes: cache.map_or(0, RecordCache::bytes),
+			record_block_offset: mdx.record_block_offset,
+		}
+	}
+
+	/// file offset where record blocks start, for tools reading the raw
+	/// format
+	#[inline]
+	pub fn record_data_offset(&self) -> u64
+	{
+		self.mdx.record_block_offset
+	}
+
+	#[inline]
+	pub fn key_block_count(&self) -> usize
+	{
+		self.mdx.key_blocks.len()
+	}
+
+	#[inline]
+	pub fn record_block_count(&self) -> usize
+	{
+		self.mdx.records_info.len()
+	}
+
+	/// key blocks of the dictionary, for debugging missing words
+	pub fn dump_key_blocks(&self) -> Vec<KeyBlockRange>
+	{
+		self.mdx.key_blocks.as_ref().clone()
+	}
+
 	pub fn title(&self) -> &str
 	{
-		&self.mdx.title
+		&self.mdx.info.title
+	}
+
+	/// description from header, empty if absent
+	#[inline]
+	pub fn description(&self) -> &str
+	{
+		&self.mdx.info.description
+	}
+
+	pub fn info(&self) -> &DictInfo
+	{
+		&self.mdx.info
+	}
+
+	/// find the headword whose record contains the given offset
+	/// of the decompressed record data, O(n), for diagnostics only.
+	/// None for offsets past the end of the records
+	pub fn key_for_record_offset(&self, offset: usize) -> Option<&str>
+	{
+		let entry = self.mdx.key_entries
+			.iter()
+			.filter(|entry| entry.offset <= offset)
+			.max_by_key(|entry| entry.offset)?;
+		// the record ends where the next one starts, the last at the end
+		// of all records
+		let end = self.mdx.key_entries
+			.iter()
+			.map(|next| next.offset)
+			.filter(|next| *next > entry.offset)
+			.min()
+			.unwrap_or_else(|| self.mdx.records_info
+				.iter()
+				.map(|info| info.decompressed_size)
+				.sum());
+		(offset < end).then_some(entry.text.as_str())
+	}
+
+	/// total bytes of all headword text held in memory
+	pub fn key_entries_text_bytes(&self) -> usize
+	{
+		self.mdx.key_entries
+			.iter()
+			.map(|entry| entry.text.len())
+			.sum()
+	}
+}
+
+/// owned key and definition of all entries in key order, iterated by
+/// `for entry in &mut dict`, record blocks are decoded lazily like entries
+pub struct IntoEntries<'a>(Entries<'a>);
+
+impl Iterator for IntoEntries<'_> {
+	type Item = Result<(String, String)>;
+
+	#[inline]
+	fn next(&mut self) -> Option<Self::Item>
+	{
+		let entry = self.0.next()?;
+		Some(entry.map(|entry| (entry.key.to_owned(), entry.definition)))
+	}
+}
+
+impl<'a, M: KeyMaker> IntoIterator for &'a mut MDict<M> {
+	type Item = Result<(String, String)>;
+	type IntoIter = IntoEntries<'a>;
+
+	#[inline]
+	fn into_iter(self) -> Self::IntoIter
+	{
+		IntoEntries(self.all_entries())
+	}
+}
+
+/// reads records of a dictionary keeping the last decoded block
+struct BlockReader<'a> {
+	records_info: &'a [BlockEntryInfo],
+	reader: &'a mut Reader,
+	record_block_offset: u64,
+	encoding: &'static Encoding,
+	limits: Limits,
+	/// blocks cached by lookups or preload are read from here first
+	cache: Option<&'a mut RecordCache>,
+	// buf offset and data of the last decoded record block
+	block: Option<(usize, Vec<u8>)>,
+}
+
+impl<'a> BlockReader<'a> {
+	/// block reader of mdx along with its key entries
+	fn split(mdx: &'a mut Mdx) -> (&'a [KeyEntry], Self)
+	{
+		let reader = BlockReader {
+			records_info: &mdx.records_info,
+			reader: reader_mut(&mut mdx.reader),
+			record_block_offset: mdx.record_block_offset,
+			encoding: mdx.encoding,
+			limits: mdx.limits,
+			cache: mdx.record_cache.as_mut(),
+			block: None,
+		};
+		(&mdx.key_entries, reader)
+	}
+
+	fn definition(&mut self, entry: &KeyEntry) -> Result<String>
+	{
+		let offset = record_offset(self.records_info, entry)
+			.ok_or(Error::InvalidData)?;
+		let range = offset.block_offset..offset.entry_end;
+		let cached = self.cache
+			.as_mut()
+			.and_then(|cache| cache.get(&offset.buf_offset));
+		if let Some(data) = cached {
+			return decode_definition(data.get(range).ok_or(Error::InvalidData)?, self.encoding);
+		}
+		let data = match &self.block {
+			Some((buf_offset, data)) if *buf_offset == offset.buf_offset => data,
+			_ => {
+				let data = read_record(
+					self.reader,
+					self.record_block_offset,
+					&offset,
+					&self.limits)?;
+				&self.block.insert((offset.buf_offset, data)).1
+			}
+		};
+		decode_definition(data.get(range).ok_or(Error::InvalidData)?, self.encoding)
+	}
+}
+
+struct Entries<'a> {
+	entries: std::slice::Iter<'a, KeyEntry>,
+	records: BlockReader<'a>,
+}
+
+impl<'a> Entries<'a> {
+	/// entries of mdx in key order from the nth
+	fn new(mdx: &'a mut Mdx, first: usize) -> Self
+	{
+		let (entries, records) = BlockReader::split(mdx);
+		Entries { entries: entries[first..].iter(), records }
+	}
+}
+
+impl<'a> Iterator for Entries<'a> {
+	type Item = Result<WordDefinition<'a>>;
+
+	fn next(&mut self) -> Option<Self::Item>
+	{
+		let entry = self.entries.next()?;
+		Some(self.records.definition(entry)
+			.map(|definition| WordDefinition { key: &entry.text, definition }))
+	}
+}
+
+/// lookup word in dictionaries in priority order, each definition found
+/// comes with the index of its dictionary, stops at the first error
+pub fn lookup_multi_dict<'a, M: KeyMaker>(dicts: &mut [MDict<M>], word: &'a str)
+	-> Result<Vec<(usize, WordDefinition<'a>)>>
+{
+	let mut definitions = vec![];
+	for (idx, dict) in dicts.iter_mut().enumerate() {
+		definitions.extend(dict.lookup(word)?.into_iter().map(|definition| (idx, definition)));
+	}
+	Ok(definitions)
+}
+
+struct RecordBlocks<'a> {
+	records_info: std::slice::Iter<'a, BlockEntryInfo>,
+	reader: &'a mut Reader,
+	record_block_offset: u64,
+	limits: Limits,
+	buf_offset: usize,
+}
+
+impl Iterator for RecordBlocks<'_> {
+	type Item = Result<Vec<u8>>;
+
+	fn next(&mut self) -> Option<Self::Item>
+	{
+		let info = self.records_info.next()?;
+		let offset = RecordOffset {
+			buf_offset: self.buf_offset,
+			block_offset: 0,
+			entry_end: info.decompressed_size,
+			record_size: info.compressed_size,
+			decomp_size: info.decompressed_size,
+		};
+		self.buf_offset += info.compressed_size;
+		Some(read_record(
+			self.reader,
+			self.record_block_offset,
+			&offset,
+			&self.limits))
+	}
+}
+
+impl<M: KeyMaker + Clone> MDict<M> {
+	/// open the files again for a new instance sharing nothing mutable,
+	/// parsed keys are copied and caches start empty. Dictionaries built
+	/// from a reader have no file to open and fail with Error::NoFilePath.
+	pub fn try_clone(&self) -> Result<MDict<M>>
+	{
+		Ok(MDict {
+			mdx: self.mdx.try_clone()?,
+			merged: self.merged.iter().map(Mdx::try_clone).collect::<Result<_>>()?,
+			resources: self.resources.iter().map(Mdx::try_clone).collect::<Result<_>>()?,
+			key_maker: self.key_maker.clone(),
+			max_redirect_depth: self.max_redirect_depth,
+		})
+	}
+}
+
+impl Mdx {
+	/// parse the file again with the same settings and an empty cache
+	fn reloaded(&self, key_maker: &dyn KeyMaker) -> Result<Mdx>
+	{
+		let path = self.path.as_ref().ok_or(Error::NoFilePath)?;
+		let reader = Box::new(BufReader::new(File::open(path)?));
+		let mut mdx = load(
+			reader,
+			self.encoding_override,
+			0,
+			key_maker,
+			self.resource,
+			&self.limits,
+			&|_| {})
+			.map_err(|err| err.context(path.display().to_string()))?;
+		mdx.record_cache = self.record_cache.as_ref().map(RecordCache::empty_clone);
+		mdx.path = Some(path.clone());
+		Ok(mdx)
+	}
+
+	/// sum of decompressed size of all record blocks
+	pub fn total_decompressed_size(&self) -> usize
+	{
+		self.records_info
+			.iter()
+			.map(|info| info.decompressed_size)
+			.sum()
+	}
+
+	/// true if the header records no entries
+	#[inline]
+	pub fn is_empty(&self) -> bool
+	{
+		self.entry_count == 0
+	}
+
+	/// free all cached record blocks, the cache is still used afterwards
+	#[inline]
+	pub fn cache_clear(&mut self)
+	{
+		if let Some(cache) = &mut self.record_cache {
+			cache.clear();
+		}
+	}
+
+	/// sum of compressed size of all record blocks
+	pub fn total_compressed_size(&self) -> usize
+	{
+		self.records_info
+			.iter()
+			.map(|info| info.compressed_size)
+			.sum()
+	}
+
+	fn try_clone(&self) -> Result<Mdx>
+	{
+		let path = self.path.as_ref().ok_or(Error::NoFilePath)?;
+		let reader = Box::new(BufReader::new(File::open(path)?));
+		Ok(Mdx {
+			encoding: self.encoding,
+			info: self.info.clone(),
+			version: self.version,
+			encrypted: self.encrypted,
+			entry_count: self.entry_count,
+			key_blocks: Arc::clone(&self.key_blocks),
+			key_entries: Arc::clone(&self.key_entries),
+			records_info: Arc::clone(&self.records_info),
+			reader: Mutex::new(reader),
+			record_block_offset: self.record_block_offset,
+			limits: self.limits,
+			encoding_override: self.encoding_override,
+			resource: self.resource,
+			record_cache: self.record_cache.as_ref().map(RecordCache::empty_clone),
+			path: self.path.clone(),
+		})
+	}
+}
+
+impl TryFrom<PathBuf> for MDict<DefaultKeyMaker> {
+	type Error = Error;
+
+	/// load with default settings of MDictBuilder
+	#[inline]
+	fn try_from(path: PathBuf) -> Result<Self>
+	{
+		MDictBuilder::new(path).build()
+	}
+}
+
+impl TryFrom<&str> for MDict<DefaultKeyMaker> {
+	type Error = Error;
+
+	/// load with default settings of MDictBuilder
+	#[inline]
+	fn try_from(path: &str) -> Result<Self>
+	{
+		MDictBuilder::new(path).build()
+	}
+}
+
+/// nth key in key order, a convenience for quick scripts panicking when
+/// out of range. Definitions have no such sugar: `Index<&str>` can not be
+/// implemented, lookup needs `&mut self` and returns owned definitions.
+impl<M: KeyMaker> Index<usize> for MDict<M> {
+	type Output = str;
+
+	#[inline]
+	fn index(&self, index: usize) -> &str
+	{
+		&self.mdx.key_entries[index].text
+	}
+}
+
+impl<M: KeyMaker> Display for MDict<M> {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result
+	{
+		write!(f, "{} (MDict v{}, {} entries, encrypted: {}, {} mdd files)",
+			self.mdx.info.title,
+			self.mdx.version.number(),
+			self.mdx.entry_count,
+			self.mdx.encrypted != 0,
+			self.resources.len())
+	}
+}
+
+impl<M: KeyMaker> Debug for MDict<M> {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result
+	{
+		f.debug_struct("MDict")
+			.field("mdx", &self.mdx)
+			.field("merged", &self.merged)
+			.field("resources", &self.resources)
+			.finish()
+	}
+}
+
+impl Debug for Mdx {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result
+	{
+		let cache = self.record_cache
+			.as_ref()
+			.map(|cache| format!("{} blocks, {} bytes", cache.len(), cache.bytes()));
+		f.debug_struct("Mdx")
+			.field("title", &self.info.title)
+			.field("path", &self.path)
+			.field("encoding", &self.encoding.name())
+			.field("version", &self.version.number())
+			.field("encrypted", &self.encrypted)
+			.field("entries", &self.entry_count)
+			.field("record_blocks", &self.records_info.len())
+			.field("cache", &cache)
+			.finish()
+	}
+}
+
+/// default limit of decompressed block size, 64 MiB
+const DEFAULT_MAX_BLOCK_SIZE: usize = 64 * 1024 * 1024;
+/// default limit of headword length in chars
+const DEFAULT_MAX_KEY_LENGTH: usize = 4096;
+/// default limit of `@@@LINK=` redirects followed in a lookup
+const DEFAULT_MAX_REDIRECT_DEPTH: usize = 5;
+/// default number of decoded record blocks kept in cache
+const DEFAULT_CACHE_CAPACITY: usize = 8;
+
+enum Source {
+	Path(PathBuf),
+	/// a reader, with the path used to find resources if any,
+	/// locked only to keep the builder Sync
+	Reader(Mutex<Reader>, Option<PathBuf>),
+}
+
+impl Source {
+	/// the reader to parse and the path of dictionary if known
+	fn open(self) -> Result<(Reader, Option<PathBuf>)>
+	{
+		match self {
+			Source::Path(path) => {
+				// check file name before touching the file
+				resource_location(&path)?;
+				let f = File::open(&path)?;
+				Ok((Box::new(BufReader::new(f)), Some(path)))
+			}
+			Source::Reader(reader, path) => Ok((
+				reader.into_inner().unwrap_or_else(PoisonError::into_inner),
+				path)),
+		}
 	}
 }
 
 pub struct MDictBuilder {
-	path: PathBuf,
+	source: Source,
+	merged: Vec<PathBuf>,
+	resource_paths: Vec<PathBuf>,
 	cache_definition: bool,
 	cache_resource: bool,
+	cache_capacity: usize,
+	cache_max_bytes: Option<usize>,
+	encoding_override: Option<&'static Encoding>,
+	max_block_size: usize,
+	max_key_length: usize,
+	verify_checksum: bool,
+	normalize: bool,
+	cjk_normalize: bool,
+	case_sensitive: bool,
+	ignore_whitespace: bool,
+	ascii_fold: bool,
+	max_redirect_depth: usize,
+	mdd_globs: Vec<String>,
+	index_file: Option<PathBuf>,
 }
 
 impl MDictBuilder {
 	pub fn new(path: impl Into<PathBuf>) -> Self
+	{
+		Self::with_source(Source::Path(path.into()))
+	}
+
+	/// load dictionary from reader, path is where the dictionary would
+	/// be in file system, and only used for finding resources (.mdd)
+	pub fn from_reader<R>(reader: R, path: impl Into<PathBuf>) -> Self
+		where R: Read + Seek + Send + 'static
+	{
+		Self::with_source(Source::Reader(Mutex::new(Box::new(reader)), Some(path.into())))
+	}
+
+	/// load dictionary from memory, no resources will be loaded
+	pub fn from_bytes(bytes: Vec<u8>) -> Self
+	{
+		Self::with_source(Source::Reader(Mutex::new(Box::new(Cursor::new(bytes))), None))
+	}
+
+	/// load dictionary from static data with default options,
+	/// without a file name no resources will be loaded
+	#[inline]
+	pub fn build_from_bytes(data: &'static [u8]) -> Result<MDict<DefaultKeyMaker>>
+	{
+		Self::with_source(Source::Reader(Mutex::new(Box::new(Cursor::new(data))), None)).build()
+	}
+
+	fn with_source(source: Source) -> Self
 	{
 		MDictBuilder {
-			path: path.into(),
+			source,
+			merged: vec![],
+			resource_paths: vec![],
 			cache_definition: false,
 			cache_resource: false,
+			cache_capacity: DEFAULT_CACHE_CAPACITY,
+			cache_max_bytes: None,
+			encoding_override: None,
+			max_block_size: DEFAULT_MAX_BLOCK_SIZE,
+			max_key_length: DEFAULT_MAX_KEY_LENGTH,
+			verify_checksum: true,
+			normalize: true,
+			cjk_normalize: false,
+			case_sensitive: false,
+			ignore_whitespace: false,
+			ascii_fold: false,
+			max_redirect_depth: DEFAULT_MAX_REDIRECT_DEPTH,
+			mdd_globs: vec![],
+			index_file: None,
 		}
 	}
 
+	/// merge another dictionary, only lookup and contains query merged dictionaries,
+	/// resources are searched in all of them
+	#[inline]
+	pub fn add_mdx(mut self, path: impl Into<PathBuf>) -> Self
+	{
+		self.merged.push(path.into());
+		self
+	}
+	/// extra directory to search for resources (.mdd), after the one
+	/// where the dictionary is
+	#[inline]
+	pub fn with_resource_path(mut self, path: impl Into<PathBuf>) -> Self
+	{
+		self.resource_paths.push(path.into());
+		self
+	}
+	/// also load resources (.mdd) matching the glob pattern relative to
+	/// directory of the dictionary, like `*_images.mdd`, in sorted order
+	/// after the ones found by name
+	#[inline]
+	pub fn mdd_glob(mut self, pattern: &str) -> Self
+	{
+		self.mdd_globs.push(pattern.to_owned());
+		self
+	}
+	/// load keys and record index of the dictionary from this file instead
+	/// of parsing them, if it is newer than the dictionary and built with
+	/// the same key maker. Otherwise the dictionary is parsed and the index
+	/// written, failing to write it is ignored. Merged dictionaries and
+	/// resources are not indexed.
+	#[inline]
+	pub fn index_file(mut self, path: impl Into<PathBuf>) -> Self
+	{
+		self.index_file = Some(path.into());
+		self
+	}
 	#[inline]
 	pub fn cache_definition(mut self, cache: bool) -> Self
 	{
@@ -123,45 +1452,311 @@ impl MDictBuilder {
 		self.cache_resource = cache;
 		self
 	}
+	/// max number of decoded record blocks kept in each enabled cache,
+	/// 0 for no caching at all
+	#[inline]
+	pub fn cache_capacity(mut self, blocks: usize) -> Self
+	{
+		self.cache_capacity = blocks;
+		self
+	}
+	/// limit each enabled cache by total bytes of decoded record blocks
+	/// instead of block count, cache_capacity is ignored then unless 0
+	#[inline]
+	pub fn cache_max_bytes(mut self, bytes: usize) -> Self
+	{
+		self.cache_max_bytes = Some(bytes);
+		self
+	}
+	/// decode the dictionary with this encoding whatever the header says,
+	/// resources are not affected
+	#[inline]
+	pub fn encoding_override(mut self, encoding: &'static Encoding) -> Self
+	{
+		self.encoding_override = Some(encoding);
+		self
+	}
+	/// blocks stored or claim to decompress larger than this are rejected
+	/// before allocating, with Error::BlockTooLarge, 64 MiB by default
+	#[inline]
+	pub fn max_block_size(mut self, bytes: usize) -> Self
+	{
+		self.max_block_size = bytes;
+		self
+	}
+	/// same as max_block_size
+	#[inline]
+	pub fn with_max_definition_size(self, bytes: usize) -> Self
+	{
+		self.max_block_size(bytes)
+	}
+	/// `@@@LINK=` redirects followed by cross_reference_lookup at most,
+	/// 5 by default, 0 to return redirects without following
+	#[inline]
+	pub fn max_redirect_depth(mut self, depth: usize) -> Self
+	{
+		self.max_redirect_depth = depth;
+		self
+	}
+	/// headwords longer than this are rejected as invalid data
 	#[inline]
-	pub fn build(self) -> Result<MDict<impl KeyMaker>>
+	pub fn with_max_key_length(mut self, chars: usize) -> Self
 	{
-		self.build_with_key_maker(|key: &Cow<str>, _resource: bool| key.to_ascii_lowercase())
+		self.max_key_length = chars;
+		self
+	}
+	/// skip all checksum verification for faster loading, corrupt data
+	/// goes undetected, never use it for untrusted files
+	#[inline]
+	pub fn skip_checksum_verification(mut self, skip: bool) -> Self
+	{
+		self.verify_checksum = !skip;
+		self
 	}
+	/// skip unicode NFC normalization in the default key maker
+	#[inline]
+	pub fn no_normalize(mut self) -> Self
+	{
+		self.normalize = false;
+		self
+	}
+	/// fold keys with NFKC instead of NFC in the default key maker,
+	/// so CJK compatibility ideographs match their unified forms
+	#[inline]
+	pub fn cjk_normalize(mut self) -> Self
+	{
+		self.cjk_normalize = true;
+		self
+	}
+	/// keep keys exactly as they are in the default key maker,
+	/// no case folding nor any normalization
+	#[inline]
+	pub fn case_sensitive(mut self) -> Self
+	{
+		self.case_sensitive = true;
+		self
+	}
+	/// drop whitespace and zero-width characters from keys in the
+	/// default key maker, for headwords spaced inconsistently
+	#[inline]
+	pub fn ignore_whitespace(mut self) -> Self
+	{
+		self.ignore_whitespace = true;
+		self
+	}
+	/// fold case of ascii letters only in the default key maker, faster
+	/// but "Ü" and "ü" are then different keys
+	#[inline]
+	pub fn ascii_only_case_fold(mut self) -> Self
+	{
+		self.ascii_fold = true;
+		self
+	}
+	#[inline]
+	pub fn build(self) -> Result<MDict<DefaultKeyMaker>>
+	{
+		let key_maker = self.default_key_maker();
+		self.build_with_key_maker(key_maker)
+	}
+	/// build with key makers applied in sequence, like NFC then
+	/// lowercase then CJK folding
+	#[inline]
+	pub fn chain_key_makers(self, makers: Vec<Box<dyn KeyMaker + Send + Sync>>)
+		-> Result<MDict<KeyMakerChain>>
+	{
+		self.build_with_key_maker(KeyMakerChain::new(makers))
+	}
+	/// build a dictionary reading record blocks asynchronously,
+	/// header and keys are still parsed synchronously here
+	#[cfg(feature = "async")]
+	#[inline]
+	pub fn build_async(self) -> Result<AsyncMDict<DefaultKeyMaker>>
+	{
+		let key_maker = self.default_key_maker();
+		self.build_async_with_key_maker(key_maker)
+	}
+	#[cfg(feature = "async")]
+	pub fn build_async_with_key_maker<M: KeyMaker + Send>(self, key_maker: M)
+		-> Result<AsyncMDict<M>>
+	{
+		let path = match &self.source {
+			Source::Path(path) => path.clone(),
+			Source::Reader(..) => return Err(Error::NoFilePath),
+		};
+		// the reader of mdx is only used for parsing, merged
+		// dictionaries are not supported for async lookup
+		let file = tokio::fs::File::from_std(File::open(path)?);
+		let mdict = self.build_with_key_maker(key_maker)?;
+		Ok(AsyncMDict {
+			mdx: mdict.mdx,
+			file,
+			key_maker: mdict.key_maker,
+		})
+	}
+	#[inline]
+	fn default_key_maker(&self) -> DefaultKeyMaker
+	{
+		DefaultKeyMaker {
+			normalize: self.normalize,
+			cjk: self.cjk_normalize,
+			case_sensitive: self.case_sensitive,
+			ignore_whitespace: self.ignore_whitespace,
+			ascii_fold: self.ascii_fold,
+		}
+	}
+	#[inline]
+	fn limits(&self) -> Limits
+	{
+		Limits {
+			max_block_size: self.max_block_size,
+			max_key_length: self.max_key_length,
+			verify_checksum: self.verify_checksum,
+			cache_max_bytes: self.cache_max_bytes,
+		}
+	}
+	/// dry run decoding and checking checksums of all key and record blocks
+	/// without keeping anything, merged dictionaries and resources are not
+	/// checked. Blocks failed are reported instead of returned as error.
+	pub fn validate(self) -> Result<ValidationReport>
+	{
+		let limits = self.limits();
+		let (reader, path) = self.source.open()?;
+		validate(reader, self.encoding_override, &limits)
+			.map_err(|err| match &path {
+				Some(path) => err.context(path.display().to_string()),
+				None => err,
+			})
+	}
+	/// the dictionary is Send and Sync when the key maker is, key makers
+	/// not Send are accepted under every feature
+	#[inline]
 	pub fn build_with_key_maker<M: KeyMaker>(self, key_maker: M)
 		-> Result<MDict<M>>
 	{
-		let path = self.path;
-		let f = File::open(&path)?;
-		let reader = BufReader::new(f);
-		let cwd = path.parent()
-			.ok_or_else(|| Error::InvalidPath(path.clone()))?
-			.canonicalize()?;
-		let mdx = load(
-			reader,
-			UTF_16LE,
-			self.cache_definition,
-			&key_maker,
-			false)?;
-		let filename = path.file_stem()
-			.ok_or_else(|| Error::InvalidPath(path.clone()))?
-			.to_str()
-			.ok_or_else(|| Error::InvalidPath(path.clone()))?;
-		let resources = load_resources(
-			&cwd,
-			filename,
-			self.cache_resource,
-			&key_maker)?;
+		self.build_with_progress(key_maker, |_| {})
+	}
+	/// build reporting progress of loading the dictionary and each merged
+	/// one, for showing a progress bar. Resources are loaded silently.
+	pub fn build_with_progress<M, F>(self, key_maker: M, progress: F)
+		-> Result<MDict<M>>
+		where M: KeyMaker, F: Fn(LoadProgress)
+	{
+		let limits = self.limits();
+		let cache_capacity = |cache| if cache { self.cache_capacity } else { 0 };
+		let definition_cache = cache_capacity(self.cache_definition);
+		let resource_cache = cache_capacity(self.cache_resource);
+		let fingerprint = index_fingerprint(&key_maker, self.encoding_override, &limits, false);
+		let load_dict = |source: Source, index: Option<&Path>, resources: &mut Vec<Mdx>| {
+			// path of a reader only locates resources, it is not the dictionary
+			let from_file = matches!(source, Source::Path(_));
+			let (reader, path) = source.open()?;
+			// index only makes sense for a dictionary file to compare with
+			let index = index.zip(path.as_deref().filter(|_| from_file));
+			let indexed = index.and_then(|(index, path)| read_index(index, path, &fingerprint));
+			let rebuild_index = indexed.is_none();
+			let loaded = match indexed {
+				Some(indexed) => load_indexed(
+					reader,
+					indexed,
+					self.encoding_override,
+					definition_cache,
+					false,
+					&limits,
+					&progress),
+				None => load(
+					reader,
+					self.encoding_override,
+					definition_cache,
+					&key_maker,
+					false,
+					&limits,
+					&progress),
+			};
+			let mut mdx = loaded.map_err(|err| match &path {
+				Some(path) => err.context(path.display().to_string()),
+				None => err,
+			})?;
+			if let Some((index, path)) = index.filter(|_| rebuild_index) {
+				// the index is only a cache, the dictionary is loaded anyway
+				let _ = write_index(index, &mdx, path, &fingerprint);
+			}
+			if let Some(path) = &path {
+				if from_file {
+					mdx.path = Some(path.clone());
+				}
+				let (cwd, filename) = resource_location(path)?;
+				for dir in std::iter::once(&cwd).chain(&self.resource_paths) {
+					resources.append(&mut load_resources(
+						dir,
+						filename,
+						resource_cache,
+						&key_maker,
+						&limits)?);
+				}
+				for pattern in &self.mdd_globs {
+					let mut found = load_glob_resources(
+						&cwd,
+						pattern,
+						resources,
+						resource_cache,
+						&key_maker,
+						&limits)?;
+					resources.append(&mut found);
+				}
+			}
+			Ok::<_, Error>(mdx)
+		};
+		let max_redirect_depth = self.max_redirect_depth;
+		let mut resources = vec![];
+		let mdx = load_dict(self.source, self.index_file.as_deref(), &mut resources)?;
+		let merged = self.merged
+			.into_iter()
+			.map(|path| load_dict(Source::Path(path), None, &mut resources))
+			.collect::<Result<_>>()?;
+		progress(LoadProgress::Done);
 		Ok(MDict {
 			mdx,
+			merged,
 			resources,
 			key_maker,
+			max_redirect_depth,
 		})
 	}
 }
 
-fn load_resources(cwd: &PathBuf, name: &str, cache_resources: bool,
-	key_maker: &dyn KeyMaker) -> Result<Vec<Mdx>>
+/// directory and file stem of the dictionary for finding resources
+fn resource_location(path: &Path) -> Result<(PathBuf, &str)>
+{
+	let filename = path.file_stem()
+		.ok_or_else(|| Error::InvalidPath(path.to_path_buf()))?
+		.to_str()
+		.ok_or_else(|| Error::NonUtf8FileStem(path.to_path_buf()))?;
+	let cwd = path.parent()
+		.ok_or_else(|| Error::InvalidPath(path.to_path_buf()))?
+		.canonicalize()?;
+	Ok((cwd, filename))
+}
+
+fn load_resource(path: PathBuf, cache_capacity: usize, key_maker: &dyn KeyMaker,
+	limits: &Limits) -> Result<Mdx>
+{
+	let f = File::open(&path)?;
+	let reader = Box::new(BufReader::new(f));
+	let mut mdd = load(
+		reader,
+		None,
+		cache_capacity,
+		key_maker,
+		true,
+		limits,
+		&|_| {})
+		.map_err(|err| err.context(path.display().to_string()))?;
+	mdd.path = Some(path);
+	Ok(mdd)
+}
+
+fn load_resources(cwd: &PathBuf, name: &str, cache_capacity: usize,
+	key_maker: &dyn KeyMaker, limits: &Limits) -> Result<Vec<Mdx>>
 {
 	let mut resources = vec![];
 	// <filename>.mdd first
@@ -169,14 +1764,7 @@ fn load_resources(cwd: &PathBuf, name: &str, cache_resources: bool,
 	if !path.exists() {
 		return Ok(resources);
 	}
-	let f = File::open(&path)?;
-	let reader = BufReader::new(f);
-	resources.push(load(
-		reader,
-		UTF_16LE,
-		cache_resources,
-		key_maker,
-		true)?);
+	resources.push(load_resource(path, cache_capacity, key_maker, limits)?);
 
 	// filename.n.mdd then
 	let mut i = 1;
@@ -185,15 +1773,26 @@ fn load_resources(cwd: &PathBuf, name: &str, cache_resources: bool,
 		if !path.exists() {
 			break;
 		}
-		let f = File::open(&path)?;
-		let reader = BufReader::new(f);
-		resources.push(load(
-			reader,
-			UTF_16LE,
-			cache_resources,
-			key_maker,
-			true)?);
+		resources.push(load_resource(path, cache_capacity, key_maker, limits)?);
 		i += 1;
 	}
 	Ok(resources)
 }
+
+/// resources matching glob pattern relative to dir, in sorted order,
+/// files already loaded are skipped
+fn load_glob_resources(dir: &Path, pattern: &str, loaded: &[Mdx], cache_capacity: usize,
+	key_maker: &dyn KeyMaker, limits: &Limits) -> Result<Vec<Mdx>>
+{
+	let dir = glob::Pattern::escape(&dir.to_string_lossy());
+	let full = Path::new(&dir).join(pattern);
+	let mut paths = glob::glob(&full.to_string_lossy())
+		.map_err(Error::InvalidGlob)?
+		.collect::<std::result::Result<Vec<_>, _>>()
+		.map_err(std::io::Error::from)?;
+	paths.sort();
+	paths.retain(|path| !loaded.iter().any(|mdd| mdd.path.as_ref() == Some(path)));
+	paths.into_iter()
+		.map(|path| load_resource(path, cache_capacity, key_maker, limits))
+		.collect()
+}
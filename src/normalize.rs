@@ -0,0 +1,140 @@
+use std::borrow::Cow;
+use caseless::default_case_fold_str;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::KeyMaker;
+
+/// fold a key for lookup: NFKC maps CJK compatibility ideographs and
+/// other compatibility variants to their canonical forms, then Unicode
+/// case folding as the default key maker does
+pub fn fold_key(key: &str) -> String
+{
+	default_case_fold_str(&key.nfkc().collect::<String>())
+}
+
+/// key makers applied in sequence, each to what the previous one made,
+/// the key is kept as is without any
+#[derive(Default)]
+pub struct KeyMakerChain {
+	makers: Vec<Box<dyn KeyMaker + Send + Sync>>,
+}
+
+impl KeyMakerChain {
+	#[inline]
+	pub fn new(makers: Vec<Box<dyn KeyMaker + Send + Sync>>) -> Self
+	{
+		KeyMakerChain { makers }
+	}
+}
+
+impl KeyMaker for KeyMakerChain {
+	fn make(&self, key: &Cow<str>, resource: bool) -> String
+	{
+		let mut key = key.to_string();
+		for maker in &self.makers {
+			key = maker.make(&Cow::Owned(key), resource);
+		}
+		key
+	}
+}
+
+/// drop all unicode whitespace and zero-width characters,
+/// borrowed if there is none
+pub(crate) fn strip_whitespace(key: &str) -> Cow<'_, str>
+{
+	let ignored = |c: char| c.is_whitespace()
+		|| matches!(c, '\u{200b}' | '\u{200c}' | '\u{200d}' | '\u{2060}' | '\u{feff}');
+	if key.contains(ignored) {
+		Cow::Owned(key.chars().filter(|c| !ignored(*c)).collect())
+	} else {
+		Cow::Borrowed(key)
+	}
+}
+
+/// American Soundex code of the ascii letters in word, None without any
+fn soundex(word: &str) -> Option<String>
+{
+	fn digit(c: u8) -> u8
+	{
+		match c {
+			b'b' | b'f' | b'p' | b'v' => b'1',
+			b'c' | b'g' | b'j' | b'k' | b'q' | b's' | b'x' | b'z' => b'2',
+			b'd' | b't' => b'3',
+			b'l' => b'4',
+			b'm' | b'n' => b'5',
+			b'r' => b'6',
+			_ => b'0',
+		}
+	}
+
+	let mut letters = word.bytes()
+		.filter(u8::is_ascii_alphabetic)
+		.map(|c| c.to_ascii_lowercase());
+	let first = letters.next()?;
+	let mut code = vec![first.to_ascii_uppercase()];
+	let mut last = digit(first);
+	for c in letters {
+		// h and w do not separate letters of the same code
+		if c == b'h' || c == b'w' {
+			continue;
+		}
+		let d = digit(c);
+		if d != b'0' && d != last {
+			code.push(d);
+			if code.len() == 4 {
+				break;
+			}
+		}
+		last = d;
+	}
+	code.resize(4, b'0');
+	String::from_utf8(code).ok()
+}
+
+/// key maker matching English headwords by pronunciation with Soundex,
+/// words sounding alike share one key so lookup returns all of them.
+/// Only letters a to z are coded, so it is useless for other languages,
+/// keys without any of them and resource paths are just lowercased
+pub fn soundex_key_maker() -> impl KeyMaker
+{
+	|key: &Cow<str>, resource: bool| if resource {
+		key.to_ascii_lowercase()
+	} else {
+		soundex(key).unwrap_or_else(|| key.to_ascii_lowercase())
+	}
+}
+
+/// key maker for Chinese dictionaries matching by Pinyin, so "wu" finds
+/// "无". Han characters become toneless Pinyin of their most common
+/// reading, tone marks and tone numbers (1 to 5 after a letter) of typed
+/// Pinyin are dropped and ü is written as v, whitespace is ignored.
+#[cfg(feature = "pinyin")]
+#[derive(Debug, Clone, Default)]
+pub struct PinyinKeyMaker;
+
+#[cfg(feature = "pinyin")]
+impl KeyMaker for PinyinKeyMaker {
+	fn make(&self, key: &Cow<str>, resource: bool) -> String
+	{
+		use pinyin::ToPinyin;
+		use unicode_normalization::char::is_combining_mark;
+
+		if resource {
+			return key.to_ascii_lowercase();
+		}
+		let mut text = String::with_capacity(key.len());
+		for (c, pinyin) in key.chars().zip(key.as_ref().to_pinyin()) {
+			if let Some(pinyin) = pinyin {
+				text.extend(pinyin.plain().chars().map(|c| if c == 'ü' { 'v' } else { c }));
+			} else if matches!(c, 'ü' | 'Ü' | 'ǖ' | 'ǘ' | 'ǚ' | 'ǜ' | 'Ǖ' | 'Ǘ' | 'Ǚ' | 'Ǜ') {
+				text.push('v');
+			} else if c.is_whitespace()
+				|| matches!(c, '1'..='5') && text.ends_with(|c: char| c.is_ascii_alphabetic()) {
+				continue;
+			} else {
+				text.extend(c.nfd().filter(|c| !is_combining_mark(*c)).flat_map(char::to_lowercase));
+			}
+		}
+		text
+	}
+}
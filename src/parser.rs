@@ -1,36 +1,59 @@
 use std::borrow::Cow;
-use std::collections::hash_map::Entry;
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::fs::File;
 use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
 use adler32::RollingAdler32;
 use byteorder::{BE, ByteOrder, LE, ReadBytesExt};
 use compress::zlib;
-use encoding_rs::{Encoding, UTF_16LE, UTF_8};
+use encoding_rs::{Encoding, GB18030, GBK, UTF_16BE, UTF_16LE};
 use regex::Regex;
 use ripemd::{Digest, Ripemd128, Ripemd128Core};
-use salsa20::Salsa20;
-use salsa20::cipher::{KeyIvInit, StreamCipher};
-use salsa20::cipher::crypto_common::Output;
+use ripemd::digest::Output;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 
 use crate::{Error, mdx::Mdx, Result};
-use crate::mdx::{BlockEntryInfo, KeyEntry, KeyMaker, Reader, RecordOffset};
+use crate::cache::RecordCache;
+use crate::mdx::{BlockEntryInfo, ChecksumType, DictInfo, KeyBlockRange, KeyEntry, KeyMaker, Reader, LoadProgress, reader_mut, RecordOffset, ValidationReport, VerifyReport};
 
 #[derive(Debug)]
 struct KeyBlockHeader {
 	// block_num: usize,
-	// entry_num: usize,
+	entry_num: usize,
 	// decompressed_size: usize,
 	block_info_size: usize,
 	key_block_size: usize,
 }
 
-#[derive(Debug)]
-enum Version {
+/// sanity limits guarding against corrupt files
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Limits {
+	pub(crate) max_block_size: usize,
+	pub(crate) max_key_length: usize,
+	pub(crate) verify_checksum: bool,
+	pub(crate) cache_max_bytes: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Version {
 	V1,
 	V2,
 }
 
 impl Version {
+	#[inline]
+	pub(crate) fn number(&self) -> u8
+	{
+		match self {
+			Version::V1 => 1,
+			Version::V2 => 2,
+		}
+	}
 	#[inline]
 	fn read_number(&self, reader: &mut Reader) -> Result<usize>
 	{
@@ -66,7 +89,8 @@ struct Header {
 	version: Version,
 	encrypted: u8,
 	encoding: &'static Encoding,
-	title: String,
+	info: DictInfo,
+	export_keyword: bool,
 }
 
 #[inline]
@@ -77,32 +101,143 @@ fn read_buf(reader: &mut impl Read, len: usize) -> Result<Vec<u8>>
 	Ok(buf)
 }
 
+/// stored size of a block is checked before its buffer is allocated
 #[inline]
-fn check_adler32(data: &[u8], checksum: u32) -> Result<()>
+pub(crate) fn check_block_size(size: usize, max_size: usize) -> Result<()>
+{
+	if size > max_size {
+		Err(Error::BlockTooLarge(size))
+	} else {
+		Ok(())
+	}
+}
+
+#[inline]
+fn read_block(reader: &mut Reader, size: usize, max_size: usize) -> Result<Vec<u8>>
+{
+	check_block_size(size, max_size)?;
+	read_buf(reader, size)
+}
+
+/// read the next block for validation, one too large is skipped and None
+/// returned
+fn read_checked_block(reader: &mut Reader, size: usize, max_size: usize) -> Result<Option<Vec<u8>>>
+{
+	match read_block(reader, size, max_size) {
+		Ok(data) => Ok(Some(data)),
+		Err(Error::BlockTooLarge(_)) => {
+			reader.seek(SeekFrom::Current(i64::try_from(size).or(Err(Error::InvalidData))?))?;
+			Ok(None)
+		}
+		Err(err) => Err(err),
+	}
+}
+
+#[inline]
+fn is_utf16(encoding: &'static Encoding) -> bool
+{
+	encoding == UTF_16LE || encoding == UTF_16BE
+}
+
+#[inline]
+fn check_adler32(data: &[u8], checksum: u32, name: &'static str) -> Result<()>
 {
 	if RollingAdler32::from_buffer(data).hash() != checksum {
-		return Err(Error::InvalidCheckSum("header"));
+		return Err(Error::InvalidCheckSum(name));
+	}
+	Ok(())
+}
+
+#[inline]
+fn check_crc32(data: &[u8], checksum: u32, name: &'static str) -> Result<()>
+{
+	if crc32fast::hash(data) != checksum {
+		return Err(Error::InvalidCheckSum(name));
 	}
 	Ok(())
 }
 
-fn read_header(reader: &mut Reader, default_encoding: &'static Encoding) -> Result<Header>
+/// checksum type from the marker byte following the compress
+/// and encryption bits of a block header
+pub(crate) fn block_checksum_type(block: &[u8]) -> Result<ChecksumType>
+{
+	let marker = *block.get(3).ok_or(Error::InvalidData)?;
+	match marker {
+		0 => Ok(ChecksumType::Adler32),
+		1 => Ok(ChecksumType::Crc32),
+		_ => Err(Error::UnsupportedChecksumType(marker)),
+	}
+}
+
+/// encodings tried in order for header of old v1 files not in UTF-16LE,
+/// GB2312 is a subset of GBK
+pub(crate) const HEADER_FALLBACK_ENCODINGS: &[&Encoding] = &[GBK, GB18030];
+
+/// the xml text of header, UTF-16LE unless it has no BOM nor a readable
+/// version, then the first fallback encoding giving a v1 header is used
+pub(crate) fn read_header_text(reader: &mut impl Read, verify: bool,
+	fallbacks: &[&'static Encoding]) -> Result<String>
 {
 	let bytes = reader.read_u32::<BE>()?;
 	let info_buf = read_buf(reader, bytes as usize)?;
 	let checksum = reader.read_u32::<LE>()?;
-	check_adler32(&info_buf, checksum)?;
-
-	let info = UTF_16LE.decode(&info_buf).0;
-	let attrs = read_keys(&info);
+	if verify {
+		check_adler32(&info_buf, checksum, "header")?;
+	}
+	let text = UTF_16LE.decode(&info_buf).0.into_owned();
+	if info_buf.starts_with(&[0xff, 0xfe]) || header_version(&read_keys(&text)).is_ok() {
+		return Ok(text);
+	}
+	for encoding in fallbacks {
+		let (fallback, _, malformed) = encoding.decode(&info_buf);
+		if !malformed && matches!(header_version(&read_keys(&fallback)), Ok((1, _))) {
+			return Ok(fallback.into_owned());
+		}
+	}
+	// missing version is reported by the caller
+	Ok(text)
+}
 
+/// major version number and the full version string
+fn header_version(attrs: &HashMap<String, String>) -> Result<(u8, &str)>
+{
 	let version_str = attrs
 		.get("GeneratedByEngineVersion")
 		.ok_or(Error::NoVersion)?
 		.trim();
-	let version = version_str[0..1]
-		.parse::<u8>()
-		.or(Err(Error::InvalidVersion(version_str.to_owned())))?;
+	let version = version_str
+		.get(0..1)
+		.and_then(|major| major.parse::<u8>().ok())
+		.ok_or_else(|| Error::InvalidVersion(version_str.to_owned()))?;
+	Ok((version, version_str))
+}
+
+/// read major version of the dictionary from its header only, versions
+/// not supported for loading are returned too
+pub fn peek_version(path: impl AsRef<Path>) -> Result<u8>
+{
+	let mut reader = BufReader::new(File::open(path)?);
+	let info = read_header_text(&mut reader, true, HEADER_FALLBACK_ENCODINGS)?;
+	let (version, _) = header_version(&read_keys(&info))?;
+	Ok(version)
+}
+
+/// check if the file is a resource dictionary (.mdd) from its header only,
+/// the header of mdd is a `Library_Data` element while mdx has `Dictionary`
+pub fn is_resource_dict(path: impl AsRef<Path>) -> Result<bool>
+{
+	let mut reader = BufReader::new(File::open(path)?);
+	let info = read_header_text(&mut reader, true, HEADER_FALLBACK_ENCODINGS)?;
+	Ok(info.trim_start().starts_with("<Library_Data"))
+}
+
+fn read_header(reader: &mut Reader, default_encoding: &'static Encoding,
+	encoding_override: Option<&'static Encoding>, fallbacks: &[&'static Encoding],
+	verify: bool) -> Result<Header>
+{
+	let info = read_header_text(reader, verify, fallbacks)?;
+	let attrs = read_keys(&info);
+	let (version, version_str) = header_version(&attrs)?;
 
 
 	let title = attrs
@@ -126,7 +261,9 @@ fn read_header(reader: &mut Reader, default_encoding: &'static Encoding) -> Resu
 		})
 		.unwrap_or(0);
 
-	let encoding = if let Some(encoding) = attrs.get("Encoding") {
+	let encoding = if let Some(encoding) = encoding_override {
+		encoding
+	} else if let Some(encoding) = attrs.get("Encoding") {
 		if encoding.is_empty() {
 			default_encoding
 		} else {
@@ -136,11 +273,29 @@ fn read_header(reader: &mut Reader, default_encoding: &'static Encoding) -> Resu
 	} else {
 		default_encoding
 	};
+
+	// entries export multiple keywords separated by ';'
+	let export_keyword = attrs
+		.get("ExportKeyword")
+		.is_some_and(|x| x.trim() == "1");
+
+	let attr = |name| attrs
+		.get(name)
+		.map_or_else(String::new, |x| x.trim().to_owned());
+	let info = DictInfo {
+		title,
+		description: attr("Description"),
+		author: attr("Author"),
+		creation_date: attr("CreationDate"),
+		encoding: encoding.name().to_owned(),
+		version: version_str.to_owned(),
+	};
 	Ok(Header {
 		version,
 		encrypted,
 		encoding,
-		title,
+		info,
+		export_keyword,
 	})
 }
 
@@ -148,34 +303,36 @@ fn read_key_block_header_v1(reader: &mut Reader) -> Result<KeyBlockHeader>
 {
 	let buf = read_buf(reader, 16)?;
 	// let block_num = BE::read_u32(&buf[0..4]);
-	// let entry_num = BE::read_u32(&buf[4..8]);
+	let entry_num = BE::read_u32(&buf[4..8]);
 	let block_info_size = BE::read_u32(&buf[8..12]);
 	let key_block_size = BE::read_u32(&buf[12..16]);
 
 	Ok(KeyBlockHeader {
 		// block_num: block_num as usize,
-		// entry_num: entry_num as usize,
+		entry_num: entry_num as usize,
 		// decompressed_size: block_info_size as usize,
 		block_info_size: block_info_size as usize,
 		key_block_size: key_block_size as usize,
 	})
 }
 
-fn read_key_block_header_v2(reader: &mut Reader) -> Result<KeyBlockHeader>
+fn read_key_block_header_v2(reader: &mut Reader, verify: bool) -> Result<KeyBlockHeader>
 {
 	let buf = read_buf(reader, 40)?;
 	let checksum = reader.read_u32::<BE>()?;
-	check_adler32(&buf, checksum)?;
+	if verify {
+		check_adler32(&buf, checksum, "key block header")?;
+	}
 
 	// let block_num = BE::read_u64(&buf[0..8]);
-	// let entry_num = BE::read_u64(&buf[8..16]);
+	let entry_num = BE::read_u64(&buf[8..16]);
 	// let decompressed_size = BE::read_u64(&buf[16..24]);
 	let block_info_size = BE::read_u64(&buf[24..32]);
 	let key_block_size = BE::read_u64(&buf[32..40]);
 
 	Ok(KeyBlockHeader {
 		// block_num: block_num as usize,
-		// entry_num: entry_num as usize,
+		entry_num: entry_num as usize,
 		// decompressed_size: decompressed_size as usize,
 		block_info_size: block_info_size as usize,
 		key_block_size: key_block_size as usize,
@@ -195,7 +352,54 @@ fn fast_decrypt(encrypted: &[u8], key: &[u8]) -> Vec<u8>
 	buf
 }
 
-fn read_key_block_infos(reader: &mut Reader, size: usize, header: &Header) -> Result<Vec<BlockEntryInfo>>
+/// Salsa20/8 with zero nonce, as used by encryption method 2 of blocks.
+/// MDict derives a 16 bytes key (RIPEMD-128 of the checksum bytes, the
+/// same as fast_decrypt), which needs the "expand 16-byte k" constants
+/// the salsa20 crate doesn't provide, 32 bytes keys are accepted too.
+pub(crate) fn salsa20_8_decrypt(encrypted: &[u8], key: &[u8]) -> Vec<u8>
+{
+	let (constants, k1, k2) = if key.len() == 32 {
+		(b"expand 32-byte k", &key[..16], &key[16..])
+	} else {
+		(b"expand 16-byte k", &key[..16], &key[..16])
+	};
+	let word = |bytes: &[u8], i: usize| LE::read_u32(&bytes[i * 4..i * 4 + 4]);
+	let mut input = [0u32; 16];
+	for i in 0..4 {
+		input[i * 5] = word(constants, i);
+		input[1 + i] = word(k1, i);
+		input[11 + i] = word(k2, i);
+	}
+	// words 6, 7 are the zero nonce, 8, 9 the block counter
+
+	let mut buf = Vec::from(encrypted);
+	for (counter, chunk) in buf.chunks_mut(64).enumerate() {
+		input[8] = counter as u32;
+		input[9] = (counter as u64 >> 32) as u32;
+		let mut x = input;
+		for _ in 0..4 {
+			// column round then row round
+			for [a, b, c, d] in [[0, 4, 8, 12], [5, 9, 13, 1], [10, 14, 2, 6], [15, 3, 7, 11],
+				[0, 1, 2, 3], [5, 6, 7, 4], [10, 11, 8, 9], [15, 12, 13, 14]] {
+				x[b] ^= x[a].wrapping_add(x[d]).rotate_left(7);
+				x[c] ^= x[b].wrapping_add(x[a]).rotate_left(9);
+				x[d] ^= x[c].wrapping_add(x[b]).rotate_left(13);
+				x[a] ^= x[d].wrapping_add(x[c]).rotate_left(18);
+			}
+		}
+		let mut stream = [0u8; 64];
+		for i in 0..16 {
+			LE::write_u32(&mut stream[i * 4..], x[i].wrapping_add(input[i]));
+		}
+		for (byte, key) in chunk.iter_mut().zip(stream) {
+			*byte ^= key;
+		}
+	}
+	buf
+}
+
+fn read_key_block_infos(reader: &mut Reader, size: usize, header: &Header,
+	verify: bool) -> Result<(Vec<BlockEntryInfo>, Vec<KeyBlockRange>)>
 {
 	let buf = read_buf(reader, size)?;
 	//decrypt
@@ -221,7 +425,9 @@ fn read_key_block_infos(reader: &mut Reader, size: usize, header: &Header) -> Re
 				zlib::Decoder::new(&buf[8..])
 					.read_to_end(&mut info)?;
 			}
-			check_adler32(&info, checksum)?;
+			if verify {
+				check_adler32(&info, checksum, "key block info")?;
+			}
 			info
 		}
 	};
@@ -230,7 +436,7 @@ fn read_key_block_infos(reader: &mut Reader, size: usize, header: &Header) -> Re
 }
 
 fn decode_key_blocks(data: &[u8], header: &Header)
-	-> Result<Vec<BlockEntryInfo>>
+	-> Result<(Vec<BlockEntryInfo>, Vec<KeyBlockRange>)>
 {
 	#[inline]
 	fn read_size(data: &[u8], header: &Header) -> (usize, usize)
@@ -249,33 +455,19 @@ fn decode_key_blocks(data: &[u8], header: &Header)
 		}
 	}
 	#[inline]
-	fn text_bytes(header: &Header, bytes: usize) -> usize
-	{
-		let text_size = match header.version {
-			Version::V1 => bytes,
-			Version::V2 => bytes + 1,
-		};
-		if header.encoding == UTF_16LE {
-			text_size * 2
-		} else {
-			text_size
-		}
-	}
-	#[inline]
-	#[allow(unused)]
 	fn extract_text(data: &[u8], header: &Header, bytes: usize) -> (String, usize)
 	{
 		let text_size = match header.version {
 			Version::V1 => bytes,
 			Version::V2 => bytes + 1,
 		};
-		let bytes = if header.encoding == UTF_16LE {
+		let bytes = if is_utf16(header.encoding) {
 			text_size * 2
 		} else {
 			text_size
 		};
 		let text = header.encoding
-			.decode(&data[..text_size])
+			.decode(&data[..bytes])
 			.0
 			.trim_matches(char::from(0))
 			.to_string();
@@ -283,17 +475,18 @@ fn decode_key_blocks(data: &[u8], header: &Header)
 	}
 
 	let mut key_block_info_list = vec![];
+	let mut key_blocks = vec![];
 	let mut slice = data;
 	while !slice.is_empty() {
-		let (_num_entries, delta) = read_size(slice, header);
+		let (entry_count, delta) = read_size(slice, header);
 		slice = &slice[delta..];
 		let (bytes, delta) = read_num_bytes(slice, header);
 		slice = &slice[delta..];
-		let delta = text_bytes(header, bytes);
+		let (first_key, delta) = extract_text(slice, header, bytes);
 		slice = &slice[delta..];
 		let (bytes, delta) = read_num_bytes(slice, header);
 		slice = &slice[delta..];
-		let delta = text_bytes(header, bytes);
+		let (last_key, delta) = extract_text(slice, header, bytes);
 		slice = &slice[delta..];
 		let (compressed_size, delta) = read_size(slice, header);
 		slice = &slice[delta..];
@@ -302,40 +495,53 @@ fn decode_key_blocks(data: &[u8], header: &Header)
 		key_block_info_list.push(BlockEntryInfo {
 			compressed_size,
 			decompressed_size,
+			entry_count,
 		});
+		key_blocks.push(KeyBlockRange { first_key, last_key, entry_count });
 	}
-	Ok(key_block_info_list)
+	Ok((key_block_info_list, key_blocks))
 }
 
-fn decode_block(slice: &[u8], compressed_size: usize, decompressed_size: usize) -> Result<Vec<u8>>
+/// decode a key or record block, with verify the checksum marked in its
+/// header is checked
+pub(crate) fn decode_block(slice: &[u8], compressed_size: usize, decompressed_size: usize,
+	verify: bool, max_size: usize) -> Result<Vec<u8>>
 {
 	#[inline]
-	fn make_key(data: &[u8]) -> Output<Ripemd128Core>
+	fn make_key(checksum_bytes: &[u8]) -> Output<Ripemd128Core>
 	{
 		let mut md = Ripemd128::default();
-		md.update(&data[4..8]);
+		md.update(checksum_bytes);
 		md.finalize()
 	}
 
+	if decompressed_size > max_size {
+		return Err(Error::BlockTooLarge(decompressed_size));
+	}
+	if compressed_size < 8 || slice.len() < compressed_size {
+		return Err(Error::InvalidData);
+	}
 	let enc = LE::read_u32(&slice[0..4]);
 	let checksum_bytes = &slice[4..8];
 	let checksum = BE::read_u32(checksum_bytes);
 	let encryption_method = (enc >> 4) & 0xf;
-	// let encryption_size = (enc >> 8) & 0xff;
+	// only this many leading bytes are encrypted, 0 for the whole block
+	let encryption_size = ((enc >> 8) & 0xff) as usize;
 	let compress_method = enc & 0xf;
 
-	let encrypted = &slice[8..compressed_size];
-	let compressed: Vec<u8> = match encryption_method {
+	let data = &slice[8..compressed_size];
+	let (encrypted, plain) = if encryption_size == 0 || encryption_size >= data.len() {
+		(data, &[][..])
+	} else {
+		data.split_at(encryption_size)
+	};
+	let mut compressed: Vec<u8> = match encryption_method {
 		0 => Vec::from(encrypted),
 		1 => fast_decrypt(encrypted, make_key(checksum_bytes).as_slice()),
-		2 => {
-			let mut decrypt = Vec::from(encrypted);
-			let mut cipher = Salsa20::new(make_key(checksum_bytes).as_slice().into(), &[0; 8].into());
-			cipher.apply_keystream(&mut decrypt);
-			decrypt
-		}
+		2 => salsa20_8_decrypt(encrypted, make_key(checksum_bytes).as_slice()),
 		_ => return Err(Error::InvalidEncryptMethod(encryption_method)),
 	};
+	compressed.extend_from_slice(plain);
 
 	let decompressed = match compress_method {
 		0 => compressed,
@@ -347,26 +553,49 @@ fn decode_block(slice: &[u8], compressed_size: usize, decompressed_size: usize)
 				.or(Err(Error::InvalidData))?;
 			v
 		}
+		#[cfg(feature = "zstd")]
+		3 => zstd::bulk::decompress(&compressed, decompressed_size)
+			.or(Err(Error::InvalidData))?,
 		_ => return Err(Error::InvalidCompressMethod(compress_method)),
 	};
+	// entries are sliced by offsets in the claimed size
+	if decompressed.len() != decompressed_size {
+		return Err(Error::InvalidData);
+	}
 
-	check_adler32(&decompressed, checksum)?;
+	if verify {
+		match block_checksum_type(slice)? {
+			ChecksumType::Adler32 => check_adler32(&decompressed, checksum, "block")?,
+			ChecksumType::Crc32 => check_crc32(&decompressed, checksum, "block")?,
+		}
+	}
 	Ok(decompressed)
 }
 
-fn read_key_entries(reader: &mut Reader, size: usize, header: &Header,
-	entry_infos: Vec<BlockEntryInfo>, key_maker: &dyn KeyMaker, resource: bool)
-	-> Result<Vec<KeyEntry>>
+fn read_key_entries(data: &[u8], header: &Header, entry_infos: Vec<BlockEntryInfo>,
+	key_maker: &dyn KeyMaker, resource: bool, limits: &Limits,
+	progress: &dyn Fn(LoadProgress)) -> Result<Vec<KeyEntry>>
 {
-	let data = read_buf(reader, size)?;
-
-	let mut entries = vec![];
-	let mut slice = data.as_slice();
-	for info in entry_infos {
-		let decompressed = decode_block(
-			slice, info.compressed_size, info.decompressed_size)?;
-		slice = &slice[info.compressed_size..];
+	let mut blocks = Vec::with_capacity(entry_infos.len());
+	let mut slice = data;
+	for info in &entry_infos {
+		blocks.push((slice, info));
+		slice = slice.get(info.compressed_size..).ok_or(Error::InvalidData)?;
+	}
+	let decode = |(idx, (slice, info)): (usize, &(&[u8], &BlockEntryInfo))| decode_block(
+		slice, info.compressed_size, info.decompressed_size, limits.verify_checksum,
+		limits.max_block_size)
+		.map_err(|err| err.context(format!("key block {}", idx)));
+	// blocks decoded in parallel are collected in the original order
+	#[cfg(feature = "parallel")]
+	let decoded = blocks.par_iter().enumerate().map(decode).collect::<Vec<_>>();
+	#[cfg(not(feature = "parallel"))]
+	let decoded = blocks.iter().enumerate().map(decode);
 
+	let mut entries = Vec::with_capacity(entry_infos.iter().map(|info| info.entry_count).sum());
+	let total = entry_infos.len();
+	for (block, decompressed) in decoded.into_iter().enumerate() {
+		let decompressed = decompressed?;
 		let mut entries_slice = decompressed.as_slice();
 		while !entries_slice.is_empty() {
 			let (offset, delta) = match header.version {
@@ -375,10 +604,29 @@ fn read_key_entries(reader: &mut Reader, size: usize, header: &Header,
 			};
 			entries_slice = &entries_slice[delta..];
 			let (text, idx) = decode_slice_string(entries_slice, header.encoding)?;
-			let text = key_maker.make(&text, resource);
-			entries.push(KeyEntry { offset, text });
+			if text.chars().count() > limits.max_key_length {
+				return Err(Error::InvalidData);
+			}
+			if header.export_keyword {
+				for keyword in text.split(';').map(str::trim).filter(|k| !k.is_empty()) {
+					let text = key_maker.make(&Cow::Borrowed(keyword), resource);
+					entries.push(KeyEntry { offset, end: None, text });
+				}
+			} else {
+				let text = key_maker.make(&text, resource);
+				entries.push(KeyEntry { offset, end: None, text });
+			}
 			entries_slice = &entries_slice[idx..];
 		}
+		progress(LoadProgress::ReadingKeyBlocks { done: block + 1, total });
+	}
+	// record of an entry ends where the next record starts
+	let mut offsets: Vec<usize> = entries.iter().map(|entry| entry.offset).collect();
+	offsets.sort_unstable();
+	offsets.dedup();
+	for entry in &mut entries {
+		let next = offsets.partition_point(|offset| *offset <= entry.offset);
+		entry.end = offsets.get(next).copied();
 	}
 	entries.sort_by(|a, b| a.text.cmp(&b.text));
 
@@ -397,58 +645,125 @@ fn read_record_blocks(reader: &mut Reader, header: &Header)
 	for _i in 0..num_records {
 		let compressed_size = version.read_number(reader)?;
 		let decompressed_size = version.read_number(reader)?;
-		records.push(BlockEntryInfo { compressed_size, decompressed_size })
+		records.push(BlockEntryInfo {
+			compressed_size,
+			decompressed_size,
+			entry_count: 0,
+		})
 	}
 	Ok(records)
 }
 
-pub(crate) fn load(mut reader: Reader, default_encoding: &'static Encoding,
-	cache: bool, key_maker: &dyn KeyMaker, resource: bool) -> Result<Mdx>
+/// everything parsed from a dictionary after the header
+pub(crate) struct KeyIndex {
+	pub(crate) entry_count: usize,
+	pub(crate) key_blocks: Vec<KeyBlockRange>,
+	pub(crate) key_entries: Vec<KeyEntry>,
+	pub(crate) records_info: Vec<BlockEntryInfo>,
+	pub(crate) record_block_offset: u64,
+}
+
+pub(crate) fn load(mut reader: Reader, encoding_override: Option<&'static Encoding>, cache_capacity: usize,
+	key_maker: &dyn KeyMaker, resource: bool, limits: &Limits,
+	progress: &dyn Fn(LoadProgress)) -> Result<Mdx>
+{
+	progress(LoadProgress::ReadingHeader);
+	let header = read_header(&mut reader, UTF_16LE, encoding_override,
+		HEADER_FALLBACK_ENCODINGS, limits.verify_checksum)?;
+	let index = read_key_index(&mut reader, &header, key_maker, resource, limits, progress)?;
+	Ok(new_mdx(reader, header, index, encoding_override, cache_capacity, resource, limits))
+}
+
+/// load with keys and record index from a prebuilt index,
+/// only the header is read from the dictionary, key blocks are reported
+/// all done at once
+pub(crate) fn load_indexed(mut reader: Reader, index: KeyIndex,
+	encoding_override: Option<&'static Encoding>, cache_capacity: usize, resource: bool,
+	limits: &Limits, progress: &dyn Fn(LoadProgress)) -> Result<Mdx>
 {
-	let header = read_header(&mut reader, default_encoding)?;
+	progress(LoadProgress::ReadingHeader);
+	let header = read_header(&mut reader, UTF_16LE, encoding_override,
+		HEADER_FALLBACK_ENCODINGS, limits.verify_checksum)?;
+	let total = index.key_blocks.len();
+	progress(LoadProgress::ReadingKeyBlocks { done: total, total });
+	Ok(new_mdx(reader, header, index, encoding_override, cache_capacity, resource, limits))
+}
+
+fn read_key_index(reader: &mut Reader, header: &Header, key_maker: &dyn KeyMaker,
+	resource: bool, limits: &Limits, progress: &dyn Fn(LoadProgress)) -> Result<KeyIndex>
+{
+	let verify = limits.verify_checksum;
 	let key_block_header = match &header.version {
-		Version::V1 => read_key_block_header_v1(&mut reader)?,
-		Version::V2 => read_key_block_header_v2(&mut reader)?,
+		Version::V1 => read_key_block_header_v1(reader)?,
+		Version::V2 => read_key_block_header_v2(reader, verify)?,
 	};
-	let key_block_infos = read_key_block_infos(
-		&mut reader,
+	let (key_block_infos, key_blocks) = read_key_block_infos(
+		reader,
 		key_block_header.block_info_size,
-		&header)?;
+		header,
+		verify)?;
 
+	let key_block_data = read_buf(reader, key_block_header.key_block_size)?;
 	let key_entries = read_key_entries(
-		&mut reader,
-		key_block_header.key_block_size,
-		&header,
+		&key_block_data,
+		header,
 		key_block_infos,
 		key_maker,
-		resource)?;
+		resource,
+		limits,
+		progress)?;
 
+	progress(LoadProgress::ReadingRecordIndex);
 	let records_info = read_record_blocks(
-		&mut reader,
-		&header)?;
+		reader,
+		header)?;
 
 	let record_block_offset = reader.stream_position()?;
 
-	Ok(Mdx {
-		encoding: header.encoding,
-		title: header.title,
-		encrypted: header.encrypted,
+	Ok(KeyIndex {
+		entry_count: key_block_header.entry_num,
+		key_blocks,
 		key_entries,
 		records_info,
-		reader,
 		record_block_offset,
-		record_cache: if cache { Some(HashMap::new()) } else { None },
 	})
 }
 
-fn record_offset(records_info: &Vec<BlockEntryInfo>, entry: &KeyEntry) -> Option<RecordOffset> {
+fn new_mdx(reader: Reader, header: Header, index: KeyIndex,
+	encoding_override: Option<&'static Encoding>, cache_capacity: usize, resource: bool,
+	limits: &Limits) -> Mdx
+{
+	Mdx {
+		encoding: header.encoding,
+		info: header.info,
+		version: header.version,
+		encrypted: header.encrypted,
+		entry_count: index.entry_count,
+		key_blocks: Arc::new(index.key_blocks),
+		key_entries: Arc::new(index.key_entries),
+		records_info: Arc::new(index.records_info),
+		reader: Mutex::new(reader),
+		record_block_offset: index.record_block_offset,
+		limits: *limits,
+		encoding_override,
+		resource,
+		record_cache: RecordCache::new(cache_capacity, limits.cache_max_bytes),
+		path: None,
+	}
+}
+
+pub(crate) fn record_offset(records_info: &[BlockEntryInfo], entry: &KeyEntry) -> Option<RecordOffset> {
 	let mut block_offset = 0;
 	let mut buf_offset = 0;
 	for info in records_info {
 		if entry.offset < block_offset + info.decompressed_size {
+			let entry_end = entry.end
+				.map_or(info.decompressed_size, |end| end - block_offset)
+				.min(info.decompressed_size);
 			return Some(RecordOffset {
 				buf_offset,
 				block_offset: entry.offset - block_offset,
+				entry_end,
 				record_size: info.compressed_size,
 				decomp_size: info.decompressed_size,
 			});
@@ -460,73 +775,392 @@ fn record_offset(records_info: &Vec<BlockEntryInfo>, entry: &KeyEntry) -> Option
 	None
 }
 
-fn find_definition(mdx: &mut Mdx, offset: RecordOffset) -> Result<Cow<[u8]>>
+#[inline]
+pub(crate) fn read_record(reader: &mut Reader, record_block_offset: u64,
+	offset: &RecordOffset, limits: &Limits) -> Result<Vec<u8>>
 {
-	#[inline]
-	fn read_record(reader: &mut Reader, record_block_offset: u64,
-		offset: RecordOffset) -> Result<Vec<u8>>
-	{
-		reader.seek(SeekFrom::Start(record_block_offset + offset.buf_offset as u64))?;
-		let data = read_buf(reader, offset.record_size)?;
-		decode_block(&data, offset.record_size, offset.decomp_size)
+	reader.seek(SeekFrom::Start(record_block_offset + offset.buf_offset as u64))?;
+	let data = read_block(reader, offset.record_size, limits.max_block_size)?;
+	decode_block(&data, offset.record_size, offset.decomp_size,
+		limits.verify_checksum, limits.max_block_size)
+		.map_err(|err| err.context(format!("record block at {}", offset.buf_offset)))
+}
+
+/// decode blocks one by one as listed in infos, reporting indices of
+/// the ones failed to decode or checksum
+fn validate_blocks(reader: &mut Reader, infos: &[BlockEntryInfo], max_size: usize,
+	failed: &mut Vec<usize>) -> Result<usize>
+{
+	for (idx, info) in infos.iter().enumerate() {
+		let Some(data) = read_checked_block(reader, info.compressed_size, max_size)? else {
+			failed.push(idx);
+			continue;
+		};
+		let decoded = decode_block(&data, info.compressed_size, info.decompressed_size,
+			true, max_size);
+		if decoded.is_err() {
+			failed.push(idx);
+		}
+	}
+	Ok(infos.len())
+}
+
+pub(crate) fn validate(mut reader: Reader, encoding_override: Option<&'static Encoding>,
+	limits: &Limits) -> Result<ValidationReport>
+{
+	let header = read_header(&mut reader, UTF_16LE, encoding_override,
+		HEADER_FALLBACK_ENCODINGS, true)?;
+	let key_block_header = match &header.version {
+		Version::V1 => read_key_block_header_v1(&mut reader)?,
+		Version::V2 => read_key_block_header_v2(&mut reader, true)?,
+	};
+	let (key_block_infos, _) = read_key_block_infos(
+		&mut reader,
+		key_block_header.block_info_size,
+		&header,
+		true)?;
+	let mut report = ValidationReport::default();
+	report.key_blocks_checked = validate_blocks(&mut reader, &key_block_infos,
+		limits.max_block_size, &mut report.failed_key_blocks)?;
+	let records_info = read_record_blocks(&mut reader, &header)?;
+	report.record_blocks_checked = validate_blocks(&mut reader, &records_info,
+		limits.max_block_size, &mut report.failed_record_blocks)?;
+	Ok(report)
+}
+
+pub(crate) fn verify_records(mdx: &mut Mdx) -> Result<VerifyReport>
+{
+	let mut report = VerifyReport::default();
+	let reader = reader_mut(&mut mdx.reader);
+	reader.seek(SeekFrom::Start(mdx.record_block_offset))?;
+	for (idx, info) in mdx.records_info.iter().enumerate() {
+		report.blocks_checked += 1;
+		let decoded = read_checked_block(reader, info.compressed_size, mdx.limits.max_block_size)?
+			.ok_or(Error::BlockTooLarge(info.compressed_size))
+			// checked whatever the load time checksum setting was
+			.and_then(|data| decode_block(&data, info.compressed_size,
+				info.decompressed_size, true, mdx.limits.max_block_size));
+		// read errors are returned above, any decode error fails the block
+		if decoded.is_err() {
+			report.blocks_failed += 1;
+			report.failed_blocks.push(idx);
+		}
+	}
+	Ok(report)
+}
+
+/// decode all record blocks into cache, growing it to hold them all
+pub(crate) fn preload_records(mdx: &mut Mdx) -> Result<()>
+{
+	let Some(blocks) = NonZeroUsize::new(mdx.records_info.len()) else {
+		return Ok(());
+	};
+	let cache = match &mut mdx.record_cache {
+		Some(cache) => cache,
+		cache => cache.insert(RecordCache::new(blocks.get(), None)
+			.ok_or(Error::InvalidData)?),
+	};
+	cache.reserve(blocks);
+	let mut buf_offset = 0;
+	for info in mdx.records_info.iter() {
+		if !cache.contains(&buf_offset) {
+			let reader = reader_mut(&mut mdx.reader);
+			reader.seek(SeekFrom::Start(mdx.record_block_offset + buf_offset as u64))?;
+			let data = read_block(reader, info.compressed_size, mdx.limits.max_block_size)?;
+			let block = decode_block(&data, info.compressed_size,
+				info.decompressed_size, mdx.limits.verify_checksum, mdx.limits.max_block_size)?;
+			cache.put(buf_offset, block);
+		}
+		buf_offset += info.compressed_size;
 	}
+	Ok(())
+}
+
+fn find_definition(mdx: &mut Mdx, offset: RecordOffset) -> Result<Cow<[u8]>>
+{
 	let block_offset = offset.block_offset;
+	let entry_end = offset.entry_end;
 	if let Some(cache) = &mut mdx.record_cache {
-		let data = match cache.entry(offset.buf_offset) {
-			Entry::Occupied(o) => o.into_mut(),
-			Entry::Vacant(v) => {
-				let reader = &mut mdx.reader;
-				let decompressed = read_record(reader, mdx.record_block_offset, offset)?;
-				v.insert(decompressed)
-			}
-		};
-		Ok(Cow::Borrowed(&data[block_offset..]))
+		let reader = reader_mut(&mut mdx.reader);
+		let data = cache.try_get_or_insert(offset.buf_offset, ||
+			read_record(reader, mdx.record_block_offset, &offset, &mdx.limits))?;
+		Ok(Cow::Borrowed(data.get(block_offset..entry_end).ok_or(Error::InvalidData)?))
 	} else {
-		let reader = &mut mdx.reader;
-		let mut data = read_record(reader, mdx.record_block_offset, offset)?;
-		if block_offset != 0 {
-			data = Vec::from(&data[block_offset..]);
-		}
-		Ok(Cow::Owned(data))
+		let reader = reader_mut(&mut mdx.reader);
+		let data = read_record(reader, mdx.record_block_offset, &offset, &mdx.limits)?;
+		let slice = data.get(block_offset..entry_end).ok_or(Error::InvalidData)?;
+		Ok(Cow::Owned(slice.to_vec()))
 	}
 }
 
 pub(crate) fn lookup_record<'a>(mdx: &'a mut Mdx, key: &str) -> Result<Option<Cow<'a, [u8]>>>
 {
-	if let Ok(idx) = mdx.key_entries.binary_search_by(|entry| entry.text.as_str().cmp(key)) {
-		let entry = &mdx.key_entries[idx];
-		if let Some(offset) = record_offset(&mdx.records_info, entry) {
-			let slice = find_definition(mdx, offset)?;
-			return Ok(Some(slice));
+	match key_range(&mdx.key_entries, key) {
+		range if range.is_empty() => Ok(None),
+		range => entry_record(mdx, range.start),
+	}
+}
+
+/// binary search sorted entries, cmp tells how an entry text is ordered
+/// against the target, which must already be made by the key maker.
+/// Returns the range of entries ordered equal, when nothing equals
+/// the range is empty and starts at the insertion point.
+pub(crate) fn bisect_search(entries: &[KeyEntry], cmp: impl Fn(&str) -> Ordering)
+	-> Range<usize>
+{
+	let start = entries.partition_point(|entry| cmp(&entry.text) == Ordering::Less);
+	let len = entries[start..].partition_point(|entry| cmp(&entry.text) == Ordering::Equal);
+	start..start + len
+}
+
+/// range of entries with text equal to key
+#[inline]
+pub(crate) fn key_range(entries: &[KeyEntry], key: &str) -> Range<usize>
+{
+	bisect_search(entries, |text| text.cmp(key))
+}
+
+/// first entry with text equal to key. Keys of all blocks are kept in one
+/// sorted list, so this is the single level of search there is.
+#[inline]
+pub(crate) fn find_entry<'a>(entries: &'a [KeyEntry], key: &str) -> Option<&'a KeyEntry>
+{
+	let range = key_range(entries, key);
+	entries[range].first()
+}
+
+/// range of entries with text starting with prefix, entries are sorted,
+/// so all matches are adjacent
+#[inline]
+pub(crate) fn prefix_range(entries: &[KeyEntry], prefix: &str) -> Range<usize>
+{
+	bisect_search(entries, |text| if text.starts_with(prefix) {
+		Ordering::Equal
+	} else {
+		text.cmp(prefix)
+	})
+}
+
+/// match text against pattern with `*` for any sequence and `?` for any char
+pub(crate) fn wildcard_match(pattern: &str, text: &str) -> bool
+{
+	let pattern: Vec<char> = pattern.chars().collect();
+	let text: Vec<char> = text.chars().collect();
+	let mut p = 0;
+	let mut t = 0;
+	// position of last '*' in pattern and the text position it matched to
+	let mut star = None;
+	while t < text.len() {
+		if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+			p += 1;
+			t += 1;
+		} else if p < pattern.len() && pattern[p] == '*' {
+			star = Some((p, t));
+			p += 1;
+		} else if let Some((star_p, star_t)) = star {
+			// let the last '*' eat one more char
+			p = star_p + 1;
+			t = star_t + 1;
+			star = Some((star_p, t));
+		} else {
+			return false;
 		}
 	}
-	Ok(None)
+	pattern[p..].iter().all(|c| *c == '*')
 }
 
-pub(crate) fn decode_slice_string<'a>(slice: &'a [u8],
-	encoding: &'static Encoding) -> Result<(Cow<'a, str>, usize)>
+/// Levenshtein distance between a and b counted in chars, Wagner-Fischer
+/// keeping a single row
+pub(crate) fn edit_distance(a: &str, b: &str) -> usize
 {
-	let (idx, delta) = if encoding == UTF_16LE {
-		let mut found = None;
-		for i in (0..slice.len()).step_by(2) {
-			if slice[i] == 0 && slice[i + 1] == 0 {
-				found = Some(i);
-				break;
+	let b: Vec<char> = b.chars().collect();
+	let mut row: Vec<usize> = (0..=b.len()).collect();
+	for (i, ca) in a.chars().enumerate() {
+		// distance of the previous row at j - 1
+		let mut diagonal = row[0];
+		row[0] = i + 1;
+		for (j, cb) in b.iter().enumerate() {
+			let substitution = diagonal + usize::from(ca != *cb);
+			diagonal = row[j + 1];
+			row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+		}
+	}
+	row[b.len()]
+}
+
+/// resource keys in mdd are windows style paths like `\\img\\a.png`,
+/// accept `/` as separator and a missing leading separator too
+pub(crate) fn resource_path(path: &str) -> Cow<'_, str>
+{
+	if path.starts_with('\\') && !path.contains('/') {
+		return Cow::Borrowed(path);
+	}
+	let path = path.replace('/', "\\");
+	if path.starts_with('\\') {
+		Cow::Owned(path)
+	} else {
+		Cow::Owned(format!("\\{}", path))
+	}
+}
+
+/// strip the html and body tags wrapping the whole definition
+pub(crate) fn strip_html_wrapper(text: &str) -> &str
+{
+	let mut text = text.trim();
+	for tag in ["html", "body"] {
+		let close = format!("</{}>", tag);
+		let opened = text.get(..tag.len() + 1)
+			.is_some_and(|open| open.eq_ignore_ascii_case(&format!("<{}", tag)))
+			&& text[tag.len() + 1..].starts_with(|c: char| c == '>' || c.is_ascii_whitespace());
+		let closed = text.len() >= close.len() && text.get(text.len() - close.len()..)
+			.is_some_and(|end| end.eq_ignore_ascii_case(&close));
+		if !opened || !closed {
+			continue;
+		}
+		if let Some(start) = text.find('>') {
+			let end = text.len() - close.len();
+			if start < end {
+				text = text[start + 1..end].trim();
 			}
 		}
-		if let Some(idx) = found {
-			(idx, 2)
+	}
+	text
+}
+
+/// plain text approximation of a html definition: tags are dropped along
+/// with script and style content, `<br>` becomes a line break and `<p>`
+/// a blank line, common entities are decoded
+pub(crate) fn html_to_text(html: &str) -> String
+{
+	let mut text = String::with_capacity(html.len());
+	let mut rest = html;
+	// inside script or style, skipped until its closing tag
+	let mut skipping: Option<&str> = None;
+	while let Some(c) = rest.chars().next() {
+		if c == '<' && rest[1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '/' || c == '!') {
+			let end = tag_end(rest);
+			let tag = rest[1..end].trim_end_matches('>');
+			let closing = tag.starts_with('/');
+			let name = tag.trim_start_matches('/')
+				.split(|c: char| c.is_ascii_whitespace() || c == '/')
+				.next()
+				.unwrap_or("")
+				.to_ascii_lowercase();
+			match skipping {
+				Some(skipped) => if closing && name == skipped {
+					skipping = None;
+				}
+				None => match name.as_str() {
+					"br" => text.push('\n'),
+					"p" if !closing => text.push_str("\n\n"),
+					"script" if !closing => skipping = Some("script"),
+					"style" if !closing => skipping = Some("style"),
+					_ => {}
+				}
+			}
+			rest = &rest[end..];
+		} else if skipping.is_some() {
+			rest = &rest[c.len_utf8()..];
+		} else if c == '&' {
+			match decode_entity(rest) {
+				Some((decoded, len)) => {
+					text.push(decoded);
+					rest = &rest[len..];
+				}
+				None => {
+					text.push('&');
+					rest = &rest[1..];
+				}
+			}
 		} else {
-			return Err(Error::InvalidData);
+			text.push(c);
+			rest = &rest[c.len_utf8()..];
 		}
-	} else if encoding == UTF_8 {
+	}
+	text.trim().to_owned()
+}
+
+/// length of the tag at the start of html up to and including `>`,
+/// quoted attribute values may contain `>`
+fn tag_end(html: &str) -> usize
+{
+	let mut quote = None;
+	for (idx, c) in html.char_indices() {
+		match (quote, c) {
+			(None, '"' | '\'') => quote = Some(c),
+			(Some(q), _) if q == c => quote = None,
+			(None, '>') => return idx + 1,
+			_ => {}
+		}
+	}
+	html.len()
+}
+
+/// decode the entity at the start of text, with its length
+fn decode_entity(text: &str) -> Option<(char, usize)>
+{
+	let (end, _) = text.char_indices().take(12).find(|(_, c)| *c == ';')?;
+	let name = &text[1..end];
+	let decoded = match name {
+		"amp" => '&',
+		"lt" => '<',
+		"gt" => '>',
+		"quot" => '"',
+		"apos" => '\'',
+		"nbsp" => '\u{a0}',
+		_ => {
+			let code = name.strip_prefix('#')?;
+			let code = match code.strip_prefix(['x', 'X']) {
+				Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+				None => code.parse().ok()?,
+			};
+			char::from_u32(code)?
+		}
+	};
+	Some((decoded, end + 1))
+}
+
+pub(crate) fn entry_record(mdx: &mut Mdx, idx: usize) -> Result<Option<Cow<'_, [u8]>>>
+{
+	let entry = &mdx.key_entries[idx];
+	if let Some(offset) = record_offset(&mdx.records_info, entry) {
+		let slice = find_definition(mdx, offset)?;
+		Ok(Some(slice))
+	} else {
+		Ok(None)
+	}
+}
+
+/// decode definition of a record, null terminator is optional,
+/// trailing CR/LF is stripped but nothing else
+pub(crate) fn decode_definition(slice: &[u8], encoding: &'static Encoding)
+	-> Result<String>
+{
+	let text = match decode_slice_string(slice, encoding) {
+		Ok((text, _)) => text,
+		Err(Error::InvalidData) => encoding.decode(slice).0,
+		Err(err) => return Err(err),
+	};
+	Ok(text.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+pub(crate) fn decode_slice_string<'a>(slice: &'a [u8],
+	encoding: &'static Encoding) -> Result<(Cow<'a, str>, usize)>
+{
+	let (idx, delta) = if is_utf16(encoding) {
+		let idx = slice
+			.chunks_exact(2)
+			.position(|c| c == [0, 0])
+			.ok_or(Error::InvalidData)?;
+		(idx * 2, 2)
+	} else {
+		// UTF-8 and legacy multibyte encodings like GBK, Big5 or Shift_JIS
+		// never have a null byte inside a char
 		let idx = slice
 			.iter()
 			.position(|b| *b == 0)
 			.ok_or(Error::InvalidData)?;
 		(idx, 1)
-	} else {
-		return Err(Error::InvalidEncoding(encoding.name().to_owned()));
 	};
 
 	let text = encoding.decode(&slice[..idx]).0;
@@ -0,0 +1,66 @@
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use crate::mdx::{DefaultKeyMaker, KeyMaker, MDict, MDictBuilder, OwnedWordDefinition};
+use crate::Result;
+
+/// dictionary shared between threads, clones are cheap and all lookups
+/// through them are serialized by one lock. Calls block the thread, wrap
+/// them with spawn_blocking or the like in async code.
+pub struct SharedMDict<M: KeyMaker + Send> {
+	inner: Arc<Mutex<MDict<M>>>,
+}
+
+impl SharedMDict<DefaultKeyMaker> {
+	#[inline]
+	pub fn from_builder(builder: MDictBuilder) -> Result<Self>
+	{
+		Ok(SharedMDict::from(builder.build()?))
+	}
+}
+
+impl<M: KeyMaker + Send> SharedMDict<M> {
+	/// a panic in another thread holding the lock leaves nothing
+	/// half updated for lookups, so the poison is ignored
+	#[inline]
+	fn lock(&self) -> MutexGuard<'_, MDict<M>>
+	{
+		self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+	}
+
+	/// same as MDict::lookup, with owned definitions
+	pub fn lookup(&self, word: &str) -> Result<Vec<OwnedWordDefinition>>
+	{
+		let definitions = self.lock().lookup(word)?
+			.into_iter()
+			.map(OwnedWordDefinition::from)
+			.collect();
+		Ok(definitions)
+	}
+
+	/// same as MDict::get_resource_raw, with owned data
+	pub fn get_resource(&self, path: &str) -> Result<Option<Vec<u8>>>
+	{
+		Ok(self.lock().get_resource_raw(path)?.map(|data| data.into_owned()))
+	}
+
+	#[inline]
+	pub fn contains(&self, word: &str) -> bool
+	{
+		self.lock().contains(word)
+	}
+}
+
+impl<M: KeyMaker + Send> From<MDict<M>> for SharedMDict<M> {
+	#[inline]
+	fn from(mdict: MDict<M>) -> Self
+	{
+		SharedMDict { inner: Arc::new(Mutex::new(mdict)) }
+	}
+}
+
+impl<M: KeyMaker + Send> Clone for SharedMDict<M> {
+	#[inline]
+	fn clone(&self) -> Self
+	{
+		SharedMDict { inner: self.inner.clone() }
+	}
+}